@@ -1,14 +1,19 @@
+use std::sync::RwLock;
+use std::time::Duration;
 use std::write;
 
-use chrono::{Datelike, Months, NaiveDate, NaiveDateTime};
-use reqwest::header::HeaderMap;
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use reqwest::StatusCode;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::ofx::{
-    Ofx, OfxBankAccount, OfxCreditCard, OfxCreditCardStatement, OfxStatement, OfxStatementStatus,
-    OfxTransactionVariant, OfxTransactions,
-};
+use crate::error::Failure;
+use crate::filter::{Filter, Pipeline};
+use crate::log;
+use crate::ofx::Ofx;
+use crate::transaction::{start_of_day, Transaction, TransactionStatus, STATEMENT_TIMEZONE};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -16,10 +21,18 @@ pub struct LoginResponse {
     bearer_token: String,
 }
 
+impl LoginResponse {
+    pub fn bearer_token(&self) -> &str {
+        &self.bearer_token
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StatementResponse {
+    #[serde(default)]
     has_next: bool,
+    #[serde(default)]
     items: Vec<StatementResponseItem>,
 }
 
@@ -58,11 +71,81 @@ where
     deserializer.deserialize_str(NaiveDateTimeVisitor)
 }
 
+/// Reads an amount sent as integer cents or, should the API ever change, as a decimal string in
+/// reais such as "1234.56" or "1.234,56".
+fn from_amount<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::Number(number)) => match number.as_i64() {
+            Some(cents) => Ok(Some(cents)),
+            None => Err(serde::de::Error::custom(format!(
+                "expected an amount in cents, found {}",
+                number
+            ))),
+        },
+        Some(serde_json::Value::String(text)) => match parse_amount(&text) {
+            Some(cents) => {
+                log::info!("Warning: read amount \"{}\" as {} cents", text, cents);
+                Ok(Some(cents))
+            }
+            None => Err(serde::de::Error::custom(format!(
+                "invalid amount \"{}\"",
+                text
+            ))),
+        },
+        Some(other) => Err(serde::de::Error::custom(format!(
+            "expected an amount, found {}",
+            other
+        ))),
+    }
+}
+
+/// Parses a string amount into cents. Plain digits are already cents. Otherwise the amount is in
+/// reais, and a final '.' or ',' followed by one or two digits separates the centavos, so that
+/// both "1,234.56" and "1.234,56" are read as 123456.
+fn parse_amount(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let is_separator = |c: char| c == '.' || c == ',';
+    let decimal = digits
+        .rfind(is_separator)
+        .filter(|&i| (1..=2).contains(&(digits.len() - i - 1)));
+    let (units, fraction) = match decimal {
+        Some(i) => (&digits[..i], &digits[i + 1..]),
+        None => (digits, ""),
+    };
+    let units: String = units.chars().filter(|&c| !is_separator(c)).collect();
+    let all_digits = units
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit());
+    if units.is_empty() || !all_digits {
+        return None;
+    }
+
+    let units: i64 = units.parse().ok()?;
+    let cents = if decimal.is_some() {
+        units * 100 + format!("{:0<2}", fraction).parse::<i64>().ok()?
+    } else if digits.contains(is_separator) {
+        units * 100
+    } else {
+        units
+    };
+    Some(if negative { -cents } else { cents })
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StatementItem {
     id: Option<String>,
     action: Option<String>,
+    #[serde(default, deserialize_with = "from_amount")]
     amount: Option<i64>,
     status: Option<StatementItemStatus>,
     #[serde(deserialize_with = "from_timestamp")]
@@ -79,6 +162,9 @@ pub enum StatementItemStatus {
     Refunded,
     #[serde(rename = "PENDING")]
     Pending,
+    /// Any status we don't know about yet. These are never converted.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -86,6 +172,9 @@ pub enum StatementItemStatus {
 pub struct StatementItemData {
     merchant_name: Option<String>,
     operation_type: Option<String>,
+    /// Merchant category code, sent either as a string or a number.
+    #[serde(alias = "merchantCategoryCode")]
+    mcc: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -137,17 +226,235 @@ pub struct CajuClient {
     base_url: String,
     user_id: String,
     employee_id: String,
+    timeout: Duration,
+    user_agent: Option<String>,
+    correlation_id: Option<String>,
+    endpoints: CajuEndpoints,
+    date_format: String,
     client: reqwest::Client,
+    refresh_token: Option<String>,
+    session: RwLock<Session>,
+}
+
+/// Authentication state, replaced every time the bearer token is refreshed.
+struct Session {
+    bearer_token: Option<String>,
+    /// Whether the last refresh handed back the very token we already had, which happens when the
+    /// refresh token has expired as well.
+    refreshed_token_unchanged: bool,
+}
+
+/// Paths of the Caju API, relative to the base url. `{user_id}` and `{employee_id}` are
+/// replaced with the ids the client was built with.
+#[derive(Debug, Clone)]
+pub struct CajuEndpoints {
+    pub bearer_token: String,
+    pub statement: String,
+}
+
+impl Default for CajuEndpoints {
+    fn default() -> Self {
+        Self {
+            bearer_token: "/v1/user/{user_id}/bearer_token".to_string(),
+            statement: "/v1/employee/{employee_id}/statement".to_string(),
+        }
+    }
+}
+
+/// Format of the dates sent to Caju, e.g. `2023-06-01`.
+pub const DATE_FORMAT: &str = "%F";
+
+/// Configures a [`CajuClient`]. Anything not set keeps the defaults of [`CajuClient::builder`]:
+/// the production API and a 30 second timeout.
+pub struct CajuClientBuilder {
+    base_url: String,
+    user_id: String,
+    employee_id: String,
+    timeout: Duration,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    correlation_id: Option<String>,
+    endpoints: CajuEndpoints,
+    date_format: String,
+    http_client: Option<reqwest::Client>,
+}
+
+impl CajuClientBuilder {
+    pub fn base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Timeout of each request, applied even when a custom [`reqwest::Client`] is given.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Timeout for establishing each connection, so that an unreachable server fails fast while
+    /// slow responses still get the whole [`CajuClientBuilder::timeout`]. Not applied to a custom
+    /// [`reqwest::Client`].
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Sent in the `User-Agent` header instead of reqwest's default.
+    #[allow(dead_code)]
+    pub fn user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    /// Sent in the `x-correlation-id` header of every request, to find the requests of a run in
+    /// proxy logs.
+    pub fn correlation_id(mut self, correlation_id: String) -> Self {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    /// Paths of the API, for a proxy or mock that serves them elsewhere.
+    #[allow(dead_code)]
+    pub fn endpoints(mut self, endpoints: CajuEndpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// [`chrono::format::strftime`] format of the dates sent as statement query parameters, in
+    /// case the API starts expecting something other than [`DATE_FORMAT`].
+    #[allow(dead_code)]
+    pub fn date_format(mut self, date_format: String) -> Self {
+        self.date_format = date_format;
+        self
+    }
+
+    /// Sends requests through this client, e.g. one configured with a proxy.
+    #[allow(dead_code)]
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<CajuClient> {
+        let client = match self.http_client {
+            Some(client) => client,
+            None => {
+                let mut builder = reqwest::Client::builder().timeout(self.timeout);
+                if let Some(connect_timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(connect_timeout);
+                }
+                builder.build()?
+            }
+        };
+
+        Ok(CajuClient {
+            base_url: self.base_url,
+            user_id: self.user_id,
+            employee_id: self.employee_id,
+            timeout: self.timeout,
+            user_agent: self.user_agent,
+            correlation_id: self.correlation_id,
+            endpoints: self.endpoints,
+            date_format: self.date_format,
+            client,
+            refresh_token: None,
+            session: RwLock::new(Session {
+                bearer_token: None,
+                refreshed_token_unchanged: false,
+            }),
+        })
+    }
 }
 
 impl CajuClient {
+    /// A client for the API at `base_url`, with the defaults of [`CajuClient::builder`].
+    #[allow(dead_code)]
     pub fn new(base_url: String, user_id: String, employee_id: String) -> anyhow::Result<Self> {
-        Ok(Self {
-            base_url,
+        Self::builder(user_id, employee_id)
+            .base_url(base_url)
+            .build()
+    }
+
+    pub fn builder(user_id: String, employee_id: String) -> CajuClientBuilder {
+        CajuClientBuilder {
+            base_url: "https://apigw.caju.com.br".to_string(),
             user_id,
             employee_id,
-            client: reqwest::Client::builder().build()?,
-        })
+            timeout: Duration::from_secs(30),
+            connect_timeout: None,
+            user_agent: None,
+            correlation_id: None,
+            endpoints: CajuEndpoints::default(),
+            date_format: DATE_FORMAT.to_string(),
+            http_client: None,
+        }
+    }
+
+    /// Formats a date the way the statement endpoint expects it in its query parameters.
+    pub fn format_date(&self, date: NaiveDate) -> String {
+        date.format(&self.date_format).to_string()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}{}",
+            self.base_url,
+            path.replace("{user_id}", &self.user_id)
+                .replace("{employee_id}", &self.employee_id)
+        )
+    }
+
+    /// Adds what every request needs: the timeout, the user agent, the correlation id and, once
+    /// logged in, the bearer token.
+    fn prepare(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let mut request = request.timeout(self.timeout);
+        if let Some(user_agent) = &self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            request = request.header("x-correlation-id", correlation_id);
+        }
+        let bearer_token = self
+            .session
+            .read()
+            .expect("session lock should not be poisoned")
+            .bearer_token
+            .clone();
+        match bearer_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Timeouts are usually transient, so make it clear that the run can just be retried.
+    fn request_error(&self, error: reqwest::Error) -> anyhow::Error {
+        if error.is_timeout() {
+            let message = format!(
+                "Request to {} timed out after {:?}, try running again: {}",
+                self.base_url, self.timeout, error
+            );
+            anyhow::Error::new(error).context(message)
+        } else {
+            error.into()
+        }
+    }
+
+    fn unauthorized_error(&self) -> anyhow::Error {
+        let refreshed_token_unchanged = self
+            .session
+            .read()
+            .expect("session lock should not be poisoned")
+            .refreshed_token_unchanged;
+        if refreshed_token_unchanged {
+            anyhow::anyhow!(
+                "Caju refresh token appears expired: logging in returned the same bearer token, \
+                which is rejected. Re-capture the tokens from the Caju app"
+            )
+            .context(Failure::Auth)
+        } else {
+            anyhow::anyhow!("Caju rejected the bearer token (401 Unauthorized)")
+                .context(Failure::Auth)
+        }
     }
 
     pub async fn login(
@@ -155,10 +462,29 @@ impl CajuClient {
         existing_auth: &str,
         refresh_token: &str,
     ) -> anyhow::Result<LoginResponse> {
-        let resp: LoginResponse = self
+        self.refresh_token = Some(refresh_token.to_string());
+        self.refresh(existing_auth).await
+    }
+
+    /// Exchanges the refresh token for a new bearer token, which is used from then on.
+    async fn refresh(&self, existing_auth: &str) -> anyhow::Result<LoginResponse> {
+        let refresh_token = self
+            .refresh_token
+            .as_deref()
+            .ok_or(anyhow::anyhow!("Not logged in to Caju"))?;
+
+        let mut request = self
             .client
-            .post(format!("{}/v1/user/{}/bearer_token", self.base_url, self.user_id).as_str())
-            .header("Authorization", format!("Bearer {}", existing_auth))
+            .post(self.url(&self.endpoints.bearer_token))
+            .timeout(self.timeout)
+            .bearer_auth(existing_auth);
+        if let Some(user_agent) = &self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            request = request.header("x-correlation-id", correlation_id);
+        }
+        let resp: LoginResponse = request
             .body(
                 json!({
                     "refreshToken": refresh_token,
@@ -166,58 +492,91 @@ impl CajuClient {
                 .to_string(),
             )
             .send()
-            .await?
+            .await
+            .map_err(|e| self.request_error(e))?
             .json()
-            .await?;
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-        let mut default_headers = HeaderMap::new();
-        default_headers.append(
-            "Authorization",
-            format!("Bearer {}", resp.bearer_token).parse()?,
-        );
-
-        self.client = reqwest::Client::builder()
-            .default_headers(default_headers)
-            .build()?;
+        let mut session = self
+            .session
+            .write()
+            .expect("session lock should not be poisoned");
+        session.refreshed_token_unchanged = resp.bearer_token == existing_auth;
+        session.bearer_token = Some(resp.bearer_token.clone());
 
         Ok(resp)
     }
 
-    pub async fn get_statement(&self, query: StatementQuery) -> anyhow::Result<StatementResponse> {
+    /// Sends the request built by `build`. When Caju answers 401 the bearer token is refreshed and
+    /// the request is retried, only once, so that an expired refresh token can't loop forever.
+    async fn send_authorized<F>(&self, build: F) -> anyhow::Result<reqwest::Response>
+    where
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    {
         let response = self
-            .client
-            .get(
-                format!(
-                    "{}/v1/employee/{}/statement",
-                    self.base_url, self.employee_id
-                )
-                .as_str(),
-            )
-            .query(&[
-                ("limit", query.limit.to_string()),
-                ("cursor", query.cursor.unwrap_or_default()),
-                ("order", "DESC".to_string()),
-                (
-                    "start_date",
-                    query
-                        .start_date
-                        .map(|d| d.format("%F").to_string())
-                        .unwrap_or_default(),
-                ),
-                (
-                    "end_date",
-                    query
-                        .end_date
-                        .map(|d| d.format("%F").to_string())
-                        .unwrap_or_default(),
-                ),
-            ])
+            .prepare(build(&self.client))
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        if self.refresh_token.is_none() {
+            return Err(self.unauthorized_error());
+        }
+
+        let current_token = self
+            .session
+            .read()
+            .expect("session lock should not be poisoned")
+            .bearer_token
+            .clone()
+            .unwrap_or_default();
+        self.refresh(&current_token).await?;
+
+        let response = self
+            .prepare(build(&self.client))
             .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(self.unauthorized_error());
+        }
+
+        Ok(response)
+    }
+
+    pub async fn get_statement(&self, query: StatementQuery) -> anyhow::Result<StatementResponse> {
+        let url = self.url(&self.endpoints.statement);
+        let params = [
+            ("limit", query.limit.to_string()),
+            ("cursor", query.cursor.unwrap_or_default()),
+            ("order", "DESC".to_string()),
+            (
+                "start_date",
+                query
+                    .start_date
+                    .map(|d| self.format_date(d))
+                    .unwrap_or_default(),
+            ),
+            (
+                "end_date",
+                query
+                    .end_date
+                    .map(|d| self.format_date(d))
+                    .unwrap_or_default(),
+            ),
+        ];
+
+        let response = self
+            .send_authorized(|client| client.get(url.as_str()).query(&params))
             .await?
             .text()
-            .await?;
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-        serde_json::from_str::<StatementResponse>(&response).map_err(|e| {
+        parse_statement_response(&response).map_err(|e| {
             anyhow::anyhow!(format!(
                 "Failed to parse response: {}.\nResponse: {}",
                 e, response
@@ -225,49 +584,435 @@ impl CajuClient {
         })
     }
 
+    /// Fetches the whole statement of a month. See [`CajuClient::get_month_statement_result`] for
+    /// details about how it was fetched.
     pub async fn get_month_statement(
         &self,
         year: Option<i32>,
         month: chrono::Month,
+        max_pages: usize,
     ) -> anyhow::Result<Vec<StatementItem>> {
-        let first_day_of_month = NaiveDate::from_ymd_opt(
-            year.unwrap_or_else(|| chrono::Local::now().year()),
-            month.number_from_month(),
-            1,
-        )
+        Ok(self
+            .get_month_statement_result(year, month, None, 0, max_pages)
+            .await?
+            .items)
+    }
+
+    /// Whether a calendar month has any statement item, fetching at most one.
+    pub async fn has_month_statement(
+        &self,
+        year: i32,
+        month: chrono::Month,
+    ) -> anyhow::Result<bool> {
+        let range = statement_window(year, month, None)?;
+        let response = self
+            .get_statement(
+                StatementQuery::default()
+                    .with_date_range(Some(range))
+                    .with_limit(1),
+            )
+            .await?;
+        Ok(!response.items.is_empty())
+    }
+
+    pub async fn get_month_statement_result(
+        &self,
+        year: Option<i32>,
+        month: chrono::Month,
+        cycle_day: Option<u32>,
+        pad_days: u32,
+        max_pages: usize,
+    ) -> anyhow::Result<StatementResult> {
+        let (first_day_of_month, last_day_of_month) = statement_window(
+            year.unwrap_or_else(|| Utc::now().with_timezone(&STATEMENT_TIMEZONE).year()),
+            month,
+            cycle_day,
+        )?;
+        let fetch_range = pad_window((first_day_of_month, last_day_of_month), pad_days)?;
+
+        let pages = paginate(max_pages, move |cursor| {
+            self.get_statement(
+                StatementQuery::default()
+                    .with_date_range(Some(fetch_range))
+                    .with_cursor(cursor)
+                    .with_limit(20),
+            )
+        })
+        .await?;
+
+        Ok(StatementResult {
+            items: pages.items,
+            start_date: first_day_of_month,
+            end_date: last_day_of_month,
+            page_count: pages.count,
+            truncated: pages.truncated,
+        })
+    }
+}
+
+/// Months found to have statement items by [`probe_months`], counted in months before the month
+/// the search started from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProbedRange {
+    pub latest: u32,
+    pub earliest: u32,
+}
+
+/// Walks back from the current month (0) up to `max_lookback` months ago, asking `has_data`
+/// about each, and stops once `empty_streak` months in a row older than the latest month with
+/// data are empty. Returns None when no month had any.
+pub async fn probe_months<F, Fut>(
+    max_lookback: u32,
+    empty_streak: u32,
+    mut has_data: F,
+) -> anyhow::Result<Option<ProbedRange>>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<bool>>,
+{
+    let mut range: Option<ProbedRange> = None;
+    let mut empty = 0;
+    for months_ago in 0..=max_lookback {
+        if has_data(months_ago).await? {
+            empty = 0;
+            if let Some(range) = &mut range {
+                range.earliest = months_ago;
+            } else {
+                range = Some(ProbedRange {
+                    latest: months_ago,
+                    earliest: months_ago,
+                });
+            }
+        } else if range.is_some() {
+            empty += 1;
+            if empty >= empty_streak {
+                break;
+            }
+        }
+    }
+    Ok(range)
+}
+
+/// First and last day of the statement for a month. Without a cycle day that's the calendar
+/// month, otherwise it runs from the cycle day of the previous month up to the day before the
+/// cycle day of the given month. Cycle days past the end of a month are clamped to its last day.
+fn statement_window(
+    year: i32,
+    month: chrono::Month,
+    cycle_day: Option<u32>,
+) -> anyhow::Result<(NaiveDate, NaiveDate)> {
+    let first_day_of_month = NaiveDate::from_ymd_opt(year, month.number_from_month(), 1)
         .ok_or(anyhow::anyhow!("Failed to get current month"))?;
 
+    let Some(cycle_day) = cycle_day else {
         let last_day_of_month = first_day_of_month
             .checked_add_months(Months::new(1))
             .ok_or(anyhow::anyhow!("Failed to add a month to current month"))?
             .pred_opt()
             .ok_or(anyhow::anyhow!("Failed to get last day"))?;
+        return Ok((first_day_of_month, last_day_of_month));
+    };
 
-        let mut has_next = true;
-        let mut cursor = None;
-        let mut statements = vec![];
-        while has_next {
-            let resp = self
-                .get_statement(
-                    StatementQuery::default()
-                        .with_date_range(Some((first_day_of_month, last_day_of_month)))
-                        .with_cursor(cursor)
-                        .with_limit(20),
-                )
-                .await?;
+    let cycle_day_of = |first_day: NaiveDate| -> anyhow::Result<NaiveDate> {
+        let days_in_month = first_day
+            .checked_add_months(Months::new(1))
+            .and_then(|next| next.pred_opt())
+            .map(|last| last.day())
+            .ok_or(anyhow::anyhow!("Failed to get last day"))?;
+        first_day
+            .with_day(cycle_day.clamp(1, days_in_month))
+            .ok_or(anyhow::anyhow!("Failed to get cycle day"))
+    };
 
-            has_next = resp.has_next;
-            if let Some(first) = resp.items.last() {
-                cursor = first.cursor.clone();
-            } else {
-                break;
-            }
+    let start = cycle_day_of(
+        first_day_of_month
+            .checked_sub_months(Months::new(1))
+            .ok_or(anyhow::anyhow!(
+                "Failed to subtract a month from current month"
+            ))?,
+    )?;
+    let end = cycle_day_of(first_day_of_month)?
+        .pred_opt()
+        .ok_or(anyhow::anyhow!("Failed to get last day"))?;
+
+    Ok((start, end))
+}
+
+/// Widens a date range by `days` on each side, to catch items posted late or early relative to
+/// the requested period.
+fn pad_window(
+    (start, end): (NaiveDate, NaiveDate),
+    days: u32,
+) -> anyhow::Result<(NaiveDate, NaiveDate)> {
+    let days = Days::new(days.into());
+    Ok((
+        start
+            .checked_sub_days(days)
+            .ok_or(anyhow::anyhow!("Failed to pad the start of the statement"))?,
+        end.checked_add_days(days)
+            .ok_or(anyhow::anyhow!("Failed to pad the end of the statement"))?,
+    ))
+}
+
+/// A statement along with details about how it was fetched.
+#[derive(Debug)]
+pub struct StatementResult {
+    pub items: Vec<StatementItem>,
+    /// First day of the requested range, before any padding.
+    pub start_date: NaiveDate,
+    /// Last day of the requested range, before any padding.
+    pub end_date: NaiveDate,
+    /// Number of pages fetched from the API.
+    pub page_count: usize,
+    /// Whether pagination stopped at the page limit before reaching the end of the statement.
+    pub truncated: bool,
+}
 
-            let mut items: Vec<_> = resp.items.into_iter().map(|i| i.item).collect();
-            statements.append(&mut items);
+struct Pages {
+    items: Vec<StatementItem>,
+    count: usize,
+    truncated: bool,
+}
+
+/// Parses a statement page. Some accounts get the page wrapped in a `data` object, and a page
+/// without `items` is read as an empty last page instead of failing the whole run.
+fn parse_statement_response(response: &str) -> serde_json::Result<StatementResponse> {
+    let mut value: serde_json::Value = serde_json::from_str(response)?;
+    if let Some(data) = value
+        .get_mut("data")
+        .filter(|data| data.is_object())
+        .map(serde_json::Value::take)
+    {
+        value = data;
+    }
+    if value.get("items").is_none_or(serde_json::Value::is_null) {
+        log::info!("Warning: Caju answered a statement without items, treating it as empty");
+        if let Some(object) = value.as_object_mut() {
+            object.remove("items");
+        }
+    }
+    serde_json::from_value(value)
+}
+
+/// Follows the cursor of a paginated statement until the API says there's nothing left, or until
+/// `max_pages` pages were fetched, in which case the result is flagged as truncated.
+async fn paginate<F, Fut>(max_pages: usize, mut fetch_page: F) -> anyhow::Result<Pages>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<StatementResponse>>,
+{
+    let mut has_next = true;
+    let mut cursor = None;
+    let mut pages = Pages {
+        items: vec![],
+        count: 0,
+        truncated: false,
+    };
+    while has_next {
+        if pages.count >= max_pages {
+            pages.truncated = true;
+            break;
         }
 
-        Ok(statements)
+        let resp = fetch_page(cursor).await?;
+        pages.count += 1;
+
+        has_next = resp.has_next;
+        if let Some(first) = resp.items.last() {
+            cursor = first.cursor.clone();
+        } else {
+            break;
+        }
+
+        let mut items: Vec<_> = resp.items.into_iter().map(|i| i.item).collect();
+        pages.items.append(&mut items);
+    }
+
+    Ok(pages)
+}
+
+/// Which field of a statement item becomes the payee.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PayeeSource {
+    /// The merchant name as printed on the card statement.
+    Merchant,
+    /// The merchant name as cleaned up by Caju.
+    Normalized,
+    /// The kind of operation, such as a transfer or a deposit.
+    Operation,
+    /// The merchant name, falling back to the normalized name and then the operation.
+    #[default]
+    Auto,
+}
+
+/// How the id of a transaction, which becomes its FITID, is derived.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IdStrategy {
+    /// The id Caju gives the item. It stays the same when a pending item is confirmed, but is
+    /// empty for items sent without one.
+    #[default]
+    Provider,
+    /// A hash of the item's date, action, amount and merchant. Never empty, but it changes when
+    /// any of them does, and identical purchases made in the same second share it.
+    ContentHash,
+    /// The id Caju gives the item, or the content hash for items sent without one.
+    ProviderOrHash,
+}
+
+/// Tweaks applied when converting a Caju statement into an [`Ofx`].
+#[derive(Debug)]
+pub struct ConversionOptions {
+    normalize_payees: bool,
+    payee_from: PayeeSource,
+    trim_whitespace: bool,
+    compact_memo: bool,
+    include_raw_id: bool,
+    preserve_order: bool,
+    id_strategy: IdStrategy,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            normalize_payees: false,
+            payee_from: PayeeSource::default(),
+            trim_whitespace: true,
+            compact_memo: false,
+            include_raw_id: false,
+            preserve_order: false,
+            id_strategy: IdStrategy::default(),
+        }
+    }
+}
+
+impl ConversionOptions {
+    pub fn with_trim_whitespace(mut self, trim_whitespace: bool) -> Self {
+        self.trim_whitespace = trim_whitespace;
+        self
+    }
+
+    pub fn with_normalize_payees(mut self, normalize_payees: bool) -> Self {
+        self.normalize_payees = normalize_payees;
+        self
+    }
+
+    pub fn with_payee_from(mut self, payee_from: PayeeSource) -> Self {
+        self.payee_from = payee_from;
+        self
+    }
+
+    pub fn with_compact_memo(mut self, compact_memo: bool) -> Self {
+        self.compact_memo = compact_memo;
+        self
+    }
+
+    pub fn with_include_raw_id(mut self, include_raw_id: bool) -> Self {
+        self.include_raw_id = include_raw_id;
+        self
+    }
+
+    pub fn with_preserve_order(mut self, preserve_order: bool) -> Self {
+        self.preserve_order = preserve_order;
+        self
+    }
+
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> Self {
+        self.id_strategy = id_strategy;
+        self
+    }
+}
+
+/// Picks the payee and memo for an item. The preferred field goes first, and when a specific
+/// field was asked for, the other ones that are set are kept in the memo.
+fn describe(
+    source: PayeeSource,
+    merchant: Option<String>,
+    normalized: Option<String>,
+    operation: Option<String>,
+) -> Option<(String, String)> {
+    let candidates = match source {
+        PayeeSource::Merchant | PayeeSource::Auto => [merchant, normalized, operation],
+        PayeeSource::Normalized => [normalized, merchant, operation],
+        PayeeSource::Operation => [operation, merchant, normalized],
+    };
+    let mut candidates = candidates
+        .into_iter()
+        .flatten()
+        .filter(|field| !field.trim().is_empty());
+
+    let payee = candidates.next()?;
+    let mut memo = payee.clone();
+    if source != PayeeSource::Auto {
+        for field in candidates {
+            if !memo.split(" | ").any(|part| part == field) {
+                memo = format!("{} | {}", memo, field);
+            }
+        }
+    }
+
+    Some((payee, memo))
+}
+
+/// Packs the fields that are set into a memo like "op=PIX | ref=1234 | mcc=5812", skipping the
+/// missing ones. Returns None when none of them are set.
+fn compact_memo(fields: &[(&str, Option<&str>)]) -> Option<String> {
+    let parts: Vec<String> = fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = value.map(str::trim).filter(|value| !value.is_empty())?;
+            Some(format!("{}={}", key, value))
+        })
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" | "))
+    }
+}
+
+/// FNV-1a hash of what identifies an item besides its id, as 16 hex digits. The status is left
+/// out so that the hash survives a pending item being confirmed.
+fn content_hash(item: &StatementItem) -> String {
+    let merchant = item.data.as_ref().and_then(|d| d.merchant_name.as_deref());
+    let content = format!(
+        "{}|{}|{}|{}",
+        item.created_at,
+        item.action.as_deref().unwrap_or_default(),
+        item.amount.unwrap_or(0),
+        merchant.unwrap_or_default()
+    );
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Trims the text and collapses runs of whitespace into a single space, so that "iFood  Delivery"
+/// and "iFood Delivery " end up as the same payee.
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Uppercases the payee and strips its diacritics, so that "São Paulo" and "SAO PAULO" end up as
+/// the same payee in Actual.
+fn normalize_payee(payee: &str) -> String {
+    payee
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_uppercase()
+}
+
+impl From<StatementItemStatus> for TransactionStatus {
+    fn from(value: StatementItemStatus) -> Self {
+        match value {
+            StatementItemStatus::Confirmed => TransactionStatus::Confirmed,
+            StatementItemStatus::Refunded => TransactionStatus::Refunded,
+            StatementItemStatus::Pending => TransactionStatus::Pending,
+            StatementItemStatus::Unknown => TransactionStatus::Unknown,
+        }
     }
 }
 
@@ -275,73 +1020,1054 @@ impl TryFrom<Vec<StatementItem>> for Ofx {
     type Error = anyhow::Error;
 
     fn try_from(value: Vec<StatementItem>) -> Result<Self, Self::Error> {
-        if value.is_empty() {
-            return Err(anyhow::anyhow!("No statement to convert"));
-        }
-        let start = value.first().unwrap().created_at;
-        let end = value.last().unwrap().created_at;
-        Ok(Ofx {
-            bank: None,
-            credit_card: Some(OfxCreditCard {
-                statement: OfxCreditCardStatement {
-                    transaction_id: "transaction_id".to_string(),
-                    status: OfxStatementStatus {
-                        code: 0,
-                        severity: "INFO".to_string(),
-                    },
-                    statements: OfxStatement {
-                        currency_code: "BRL".to_string(),
-                        bank_account: OfxBankAccount {
-                            bank_id: "Caju".to_string(),
-                        },
-                        transactions: OfxTransactions {
-                            start: start.format("%Y%m%d000000[-3:BRT]").to_string(),
-                            end: end.format("%Y%m%d000000[-3:BRT]").to_string(),
-                            transactions: value
-                                .into_iter()
-                                .filter(|statement| {
-                                    statement.status == Some(StatementItemStatus::Confirmed)
-                                })
-                                .map(|statement| {
-                                    OfxTransactionVariant::Transaction(crate::ofx::OfxTransaction {
-                                        description: statement
-                                            .data
-                                            .and_then(|d| d.merchant_name)
-                                            .unwrap_or_else(|| {
-                                                if let Some(action) = statement.action.as_ref() {
-                                                    if action == "CREDIT" {
-                                                        return "Depósito em conta".to_string();
-                                                    }
-                                                }
-                                                "unknown".to_string()
-                                            }),
-                                        type_: statement
-                                            .action
-                                            .clone()
-                                            .unwrap_or("DEBIT".to_string()),
-                                        timestamp: statement
-                                            .created_at
-                                            .format("%Y%m%d000000[-3:BRT]")
-                                            .to_string(),
-                                        amount: format!(
-                                            "{:.2}",
-                                            (statement.amount.unwrap_or(0) as f64) / 100.0
-                                                * if statement.action.unwrap_or("DEBIT".to_string())
-                                                    == "DEBIT"
-                                                {
-                                                    -1.0
-                                                } else {
-                                                    1.0
-                                                }
-                                        ),
-                                        id: statement.id.unwrap_or_default(),
-                                    })
-                                })
-                                .collect(),
-                        },
-                    },
+        statement_to_ofx(value, &ConversionOptions::default())
+    }
+}
+
+pub fn statement_to_ofx(
+    value: Vec<StatementItem>,
+    options: &ConversionOptions,
+) -> anyhow::Result<Ofx> {
+    let period = statement_period(&value, None)?;
+    let mut transactions = statement_to_transactions(value, options);
+    Pipeline::default()
+        .with(Filter::Status {
+            include_pending: false,
+        })
+        .run(&mut transactions);
+
+    Ok(transactions_to_ofx(period, &transactions))
+}
+
+/// Period covered by a statement: the requested date range, if any, widened to include every
+/// item. Items come in no particular order, so this is what keeps DTSTART from being after DTEND.
+pub fn statement_period(
+    value: &[StatementItem],
+    requested: Option<(NaiveDate, NaiveDate)>,
+) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let first = value.iter().map(|i| i.created_at).min();
+    let last = value.iter().map(|i| i.created_at).max();
+    let (Some(start), Some(end)) = (first, last) else {
+        return Err(anyhow::anyhow!("No statement to convert").context(Failure::Empty));
+    };
+    let (mut start, mut end) = (Utc.from_utc_datetime(&start), Utc.from_utc_datetime(&end));
+
+    if let Some((start_date, end_date)) = requested {
+        let (requested_start, requested_end) = requested_period(start_date, end_date)?;
+        start = start.min(requested_start);
+        end = end.max(requested_end);
+    }
+
+    Ok((start, end))
+}
+
+/// DTSTART/DTEND spanning exactly the requested days, for statements whose transactions were
+/// already trimmed to them. Days start at midnight in the statement timezone.
+pub fn requested_period(
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let out_of_range = || anyhow::anyhow!("Statement period is out of range");
+    Ok((
+        start_of_day(start_date).ok_or_else(out_of_range)?,
+        start_of_day(end_date).ok_or_else(out_of_range)?,
+    ))
+}
+
+pub fn transactions_to_ofx(
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    transactions: &[Transaction],
+) -> Ofx {
+    Ofx::credit_card_statement("Caju", &start, &end, transactions)
+}
+
+/// OFX TRNTYPE for a Caju action and the sign its amount takes. Caju sends amounts unsigned,
+/// except for adjustments, which can go either way and keep the sign they come with.
+fn transaction_type(action: &str) -> Option<(&'static str, i64)> {
+    match action {
+        "DEBIT" => Some(("DEBIT", -1)),
+        "CREDIT" | "REVERSAL" => Some(("CREDIT", 1)),
+        "FEE" => Some(("FEE", -1)),
+        "ADJUSTMENT" => Some(("OTHER", 1)),
+        _ => None,
+    }
+}
+
+/// Number of items with a status we don't know about, which are skipped instead of converted.
+pub fn count_skipped(value: &[StatementItem]) -> usize {
+    value
+        .iter()
+        .filter(|statement| statement.status == Some(StatementItemStatus::Unknown))
+        .count()
+}
+
+pub fn statement_to_transactions(
+    value: Vec<StatementItem>,
+    options: &ConversionOptions,
+) -> Vec<Transaction> {
+    let unknown = count_skipped(&value);
+    if unknown > 0 {
+        log::info!("Skipping {} transactions with an unknown status", unknown);
+    }
+
+    let mut transactions: Vec<Transaction> = value
+        .into_iter()
+        .map(|statement| {
+            let hash = content_hash(&statement);
+            let (merchant, operation, mcc) = statement
+                .data
+                .map(|d| (d.merchant_name, d.operation_type, d.mcc))
+                .unwrap_or_default();
+            let category_code = match mcc {
+                Some(serde_json::Value::String(code)) if !code.trim().is_empty() => {
+                    Some(code.trim().to_string())
+                }
+                Some(serde_json::Value::Number(code)) => Some(code.to_string()),
+                _ => None,
+            };
+            let compact = if options.compact_memo {
+                compact_memo(&[
+                    ("op", operation.as_deref()),
+                    ("ref", statement.id.as_deref()),
+                    ("mcc", category_code.as_deref()),
+                ])
+            } else {
+                None
+            };
+            let (payee, memo) = describe(
+                options.payee_from,
+                merchant,
+                statement.normalized_name,
+                operation,
+            )
+            .unwrap_or_else(|| {
+                let description = match statement.action.as_deref() {
+                    Some("CREDIT") => "Depósito em conta",
+                    _ => "unknown",
+                };
+                (description.to_string(), description.to_string())
+            });
+            let memo = compact.unwrap_or(memo);
+            let memo = match (options.include_raw_id, statement.id.as_deref()) {
+                (true, Some(id)) if !id.is_empty() => format!("{} [id:{}]", memo, id),
+                _ => memo,
+            };
+            let (payee, memo) = if options.trim_whitespace {
+                (collapse_whitespace(&payee), collapse_whitespace(&memo))
+            } else {
+                (payee, memo)
+            };
+            let action = statement.action.as_deref().unwrap_or("DEBIT");
+            let (type_, sign) = transaction_type(action).unwrap_or_else(|| {
+                log::info!(
+                    "Warning: unknown Caju action {}, treating it as a debit",
+                    action
+                );
+                ("DEBIT", -1)
+            });
+            let amount = statement.amount.unwrap_or(0);
+
+            Transaction {
+                id: match (options.id_strategy, statement.id) {
+                    (IdStrategy::Provider, id) => id.unwrap_or_default(),
+                    (IdStrategy::ProviderOrHash, Some(id)) if !id.is_empty() => id,
+                    (IdStrategy::ContentHash | IdStrategy::ProviderOrHash, _) => hash,
                 },
-            }),
+                date: Utc.from_utc_datetime(&statement.created_at),
+                // Caju only reports when an item was created
+                initiated_at: None,
+                amount_cents: sign * amount,
+                type_: type_.to_string(),
+                category_code,
+                payee: if options.normalize_payees {
+                    normalize_payee(&payee)
+                } else {
+                    payee
+                },
+                memo,
+                status: statement
+                    .status
+                    .map(TransactionStatus::from)
+                    .unwrap_or(TransactionStatus::Unknown),
+            }
         })
+        .collect();
+
+    // Several items can share the same second and the API doesn't keep their order stable
+    // between requests, so break ties by id to keep the output reproducible.
+    if !options.preserve_order {
+        transactions.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.id.cmp(&b.id)));
+    }
+
+    transactions
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use chrono::NaiveDate;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+    use crate::caju::{
+        pad_window, paginate, parse_statement_response, probe_months, statement_period,
+        statement_to_ofx, statement_to_transactions, statement_window, CajuClient, CajuEndpoints,
+        ConversionOptions, IdStrategy, PayeeSource, ProbedRange, StatementItem, StatementQuery,
+        StatementResponse,
+    };
+    use crate::filter::{Filter, Pipeline};
+    use crate::ofx::{Ofx, OfxTransactionVariant};
+    use crate::transaction::TransactionStatus;
+
+    #[test]
+    fn normalize_payees() -> Result<(), anyhow::Error> {
+        let items: Vec<StatementItem> = serde_json::from_str(
+            r#"[{
+                "id": "1",
+                "action": "DEBIT",
+                "amount": 1000,
+                "status": "CONFIRMED",
+                "createdAt": "2023-06-01T12:00:00.000Z",
+                "data": { "merchantName": "São Paulo" }
+            }]"#,
+        )?;
+
+        let ofx = statement_to_ofx(
+            items,
+            &ConversionOptions::default().with_normalize_payees(true),
+        )?;
+
+        let credit_card = ofx
+            .credit_card
+            .expect("caju statements are credit card statements");
+        let OfxTransactionVariant::Transaction(transaction) =
+            &credit_card.statement.statements.transactions.transactions[0];
+
+        assert_eq!(transaction.name, "SAO PAULO");
+        assert_eq!(transaction.description, "São Paulo");
+
+        Ok(())
+    }
+
+    #[test]
+    fn trim_whitespace() -> Result<(), anyhow::Error> {
+        let fixture = r#"[{
+            "id": "1",
+            "createdAt": "2023-06-01T12:00:00.000Z",
+            "data": { "merchantName": "  iFood   \t Delivery " }
+        }]"#;
+
+        let trimmed = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default(),
+        );
+        assert_eq!(trimmed[0].payee, "iFood Delivery");
+        assert_eq!(trimmed[0].memo, "iFood Delivery");
+
+        let untouched = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default().with_trim_whitespace(false),
+        );
+        assert_eq!(untouched[0].payee, "  iFood   \t Delivery ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn pick_payee_source() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            {
+                "id": "1",
+                "createdAt": "2023-06-01T12:00:00.000Z",
+                "normalizedName": "iFood",
+                "data": { "merchantName": "IFD*IFOOD.COM", "operationType": "PURCHASE" }
+            },
+            {
+                "id": "2",
+                "createdAt": "2023-06-02T12:00:00.000Z",
+                "normalizedName": "Uber",
+                "data": { "operationType": "PURCHASE" }
+            },
+            {
+                "id": "3",
+                "action": "CREDIT",
+                "createdAt": "2023-06-03T12:00:00.000Z"
+            }
+        ]"#;
+
+        let describe = |source: PayeeSource| -> Result<Vec<(String, String)>, anyhow::Error> {
+            let transactions = statement_to_transactions(
+                serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+                &ConversionOptions::default().with_payee_from(source),
+            );
+            Ok(transactions
+                .into_iter()
+                .map(|t| (t.payee, t.memo))
+                .collect())
+        };
+        let pair = |payee: &str, memo: &str| (payee.to_string(), memo.to_string());
+
+        assert_eq!(
+            describe(PayeeSource::Auto)?,
+            vec![
+                pair("IFD*IFOOD.COM", "IFD*IFOOD.COM"),
+                pair("Uber", "Uber"),
+                pair("Depósito em conta", "Depósito em conta"),
+            ]
+        );
+        assert_eq!(
+            describe(PayeeSource::Normalized)?,
+            vec![
+                pair("iFood", "iFood | IFD*IFOOD.COM | PURCHASE"),
+                pair("Uber", "Uber | PURCHASE"),
+                pair("Depósito em conta", "Depósito em conta"),
+            ]
+        );
+        assert_eq!(
+            describe(PayeeSource::Operation)?,
+            vec![
+                pair("PURCHASE", "PURCHASE | IFD*IFOOD.COM | iFood"),
+                pair("PURCHASE", "PURCHASE | Uber"),
+                pair("Depósito em conta", "Depósito em conta"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn map_merchant_category_codes() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            { "id": "1", "createdAt": "2023-06-01T12:00:00.000Z", "data": { "mcc": "5812" } },
+            { "id": "2", "createdAt": "2023-06-02T12:00:00.000Z", "data": { "mcc": 5411 } },
+            { "id": "3", "createdAt": "2023-06-03T12:00:00.000Z", "data": {} },
+            { "id": "4", "createdAt": "2023-06-04T12:00:00.000Z" }
+        ]"#;
+
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default(),
+        );
+        let codes: Vec<_> = transactions
+            .iter()
+            .map(|t| t.category_code.as_deref())
+            .collect();
+        assert_eq!(codes, vec![Some("5812"), Some("5411"), None, None]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact_memos() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            {
+                "id": "1",
+                "createdAt": "2023-06-01T12:00:00.000Z",
+                "data": { "merchantName": "Padaria", "operationType": "PIX", "mcc": 5462 }
+            },
+            {
+                "id": "2",
+                "createdAt": "2023-06-02T12:00:00.000Z",
+                "data": { "merchantName": "Mercado", "operationType": " " }
+            },
+            { "createdAt": "2023-06-03T12:00:00.000Z", "data": { "merchantName": "Feira" } }
+        ]"#;
+
+        let options = ConversionOptions::default().with_compact_memo(true);
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &options,
+        );
+        let memos: Vec<_> = transactions.iter().map(|t| t.memo.as_str()).collect();
+        assert_eq!(memos, vec!["op=PIX | ref=1 | mcc=5462", "ref=2", "Feira"]);
+        assert_eq!(transactions[0].payee, "Padaria");
+
+        Ok(())
+    }
+
+    #[test]
+    fn append_raw_ids_to_memos() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            {
+                "id": "abc123",
+                "createdAt": "2023-06-01T12:00:00.000Z",
+                "data": { "merchantName": "Padaria" }
+            },
+            { "createdAt": "2023-06-02T12:00:00.000Z", "data": { "merchantName": "Feira" } }
+        ]"#;
+
+        let options = ConversionOptions::default().with_include_raw_id(true);
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &options,
+        );
+        assert_eq!(transactions[0].memo, "Padaria [id:abc123]");
+        assert_eq!(transactions[0].payee, "Padaria");
+        assert_eq!(transactions[1].memo, "Feira");
+
+        Ok(())
+    }
+
+    /// Items of the anonymized page in fixtures/caju_statement.json, with a refund, a pending
+    /// purchase, a deposit and accented merchant names.
+    fn fixture_items() -> Result<Vec<StatementItem>, anyhow::Error> {
+        let page: StatementResponse =
+            serde_json::from_str(include_str!("../fixtures/caju_statement.json"))?;
+        Ok(page.items.into_iter().map(|i| i.item).collect())
+    }
+
+    #[test]
+    fn convert_fixture_to_golden_ofx() -> Result<(), anyhow::Error> {
+        assert_eq!(
+            Ofx::try_from(fixture_items()?)?.to_ofx()?,
+            include_str!("../fixtures/caju_statement.ofx").trim_end()
+        );
+
+        let options = ConversionOptions::default()
+            .with_normalize_payees(true)
+            .with_payee_from(PayeeSource::Normalized);
+        assert_eq!(
+            statement_to_ofx(fixture_items()?, &options)?.to_ofx()?,
+            include_str!("../fixtures/caju_statement_normalized.ofx").trim_end()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pagination_stops_at_max_pages() -> Result<(), anyhow::Error> {
+        let mut calls = 0;
+
+        let pages = paginate(3, |_cursor| {
+            calls += 1;
+            async {
+                serde_json::from_str::<StatementResponse>(
+                    r#"{
+                        "hasNext": true,
+                        "items": [{
+                            "cursor": "next",
+                            "item": { "createdAt": "2023-06-01T12:00:00.000Z" }
+                        }]
+                    }"#,
+                )
+                .map_err(anyhow::Error::from)
+            }
+        })
+        .await?;
+
+        assert_eq!(calls, 3);
+        assert_eq!(pages.count, 3);
+        assert_eq!(pages.items.len(), 3);
+        assert!(pages.truncated);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn probe_until_months_come_up_empty() -> Result<(), anyhow::Error> {
+        // Months ago with data: a gap of two empty months at 3 and 4, then history from 5 to 8
+        let with_data = [1, 2, 5, 6, 7, 8];
+        let mut asked = vec![];
+        let range = probe_months(24, 3, |months_ago| {
+            asked.push(months_ago);
+            async move { Ok(with_data.contains(&months_ago)) }
+        })
+        .await?;
+
+        assert_eq!(
+            range,
+            Some(ProbedRange {
+                latest: 1,
+                earliest: 8
+            })
+        );
+        assert_eq!(asked, (0..=11).collect::<Vec<_>>());
+
+        let bounded = probe_months(6, 3, |months_ago| async move {
+            Ok(with_data.contains(&months_ago))
+        })
+        .await?;
+        assert_eq!(bounded.map(|range| range.earliest), Some(6));
+
+        let empty = probe_months(6, 3, |_| async { Ok(false) }).await?;
+        assert_eq!(empty, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_statement_variants() -> Result<(), anyhow::Error> {
+        let paginated = parse_statement_response(
+            r#"{
+                "hasNext": false,
+                "items": [{ "cursor": "a", "item": { "createdAt": "2023-06-01T12:00:00.000Z" } }]
+            }"#,
+        )?;
+        assert_eq!(paginated.items.len(), 1);
+
+        let wrapped = parse_statement_response(
+            r#"{
+                "data": {
+                    "hasNext": true,
+                    "items": [{ "cursor": "a", "item": { "createdAt": "2023-06-01T12:00:00.000Z" } }]
+                }
+            }"#,
+        )?;
+        assert_eq!(wrapped.items.len(), 1);
+        assert!(wrapped.has_next);
+
+        let empty = parse_statement_response(r#"{ "message": "no statement" }"#)?;
+        assert!(empty.items.is_empty());
+        assert!(!empty.has_next);
+
+        // Accounts without a card answer with null instead of a list
+        let no_card =
+            parse_statement_response(include_str!("../fixtures/caju_statement_no_card.json"))?;
+        assert!(no_card.items.is_empty());
+        assert!(!no_card.has_next);
+        let null_data = parse_statement_response(r#"{ "data": null }"#)?;
+        assert!(null_data.items.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn statement_period_is_ordered() -> Result<(), anyhow::Error> {
+        // The API returns items newest first
+        let items: Vec<StatementItem> = serde_json::from_str(
+            r#"[
+                { "createdAt": "2023-06-20T12:00:00.000Z" },
+                { "createdAt": "2023-06-10T12:00:00.000Z" }
+            ]"#,
+        )?;
+
+        let (start, end) = statement_period(&items, None)?;
+        assert!(start <= end);
+        assert_eq!(start.format("%F").to_string(), "2023-06-10");
+        assert_eq!(end.format("%F").to_string(), "2023-06-20");
+
+        let (start, end) = statement_period(
+            &items,
+            Some((
+                NaiveDate::from_ymd_opt(2023, 6, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2023, 6, 30).unwrap(),
+            )),
+        )?;
+        assert_eq!(start.format("%F").to_string(), "2023-06-01");
+        assert_eq!(end.format("%F").to_string(), "2023-06-30");
+
+        Ok(())
+    }
+
+    #[test]
+    fn align_window_to_cycle_day() -> Result<(), anyhow::Error> {
+        let day = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        assert_eq!(
+            statement_window(2024, chrono::Month::February, None)?,
+            (day(2024, 2, 1), day(2024, 2, 29))
+        );
+        assert_eq!(
+            statement_window(2024, chrono::Month::February, Some(5))?,
+            (day(2024, 1, 5), day(2024, 2, 4))
+        );
+        assert_eq!(
+            statement_window(2024, chrono::Month::March, Some(5))?,
+            (day(2024, 2, 5), day(2024, 3, 4))
+        );
+        assert_eq!(
+            statement_window(2024, chrono::Month::January, Some(5))?,
+            (day(2023, 12, 5), day(2024, 1, 4))
+        );
+        // Short months clamp the cycle day to their last day
+        assert_eq!(
+            statement_window(2023, chrono::Month::March, Some(31))?,
+            (day(2023, 2, 28), day(2023, 3, 30))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pad_window_on_both_sides() -> Result<(), anyhow::Error> {
+        let day = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        assert_eq!(
+            pad_window((day(2023, 6, 1), day(2023, 6, 30)), 2)?,
+            (day(2023, 5, 30), day(2023, 7, 2))
+        );
+        assert_eq!(
+            pad_window((day(2023, 6, 1), day(2023, 6, 30)), 0)?,
+            (day(2023, 6, 1), day(2023, 6, 30))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_statuses() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            { "id": "1", "status": "CONFIRMED", "createdAt": "2023-06-01T12:00:00.000Z" },
+            { "id": "2", "status": "PENDING", "createdAt": "2023-06-02T12:00:00.000Z" },
+            { "id": "3", "status": "REFUNDED", "createdAt": "2023-06-03T12:00:00.000Z" },
+            { "id": "4", "status": "SCHEDULED", "createdAt": "2023-06-04T12:00:00.000Z" }
+        ]"#;
+
+        let mut transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default(),
+        );
+        let statuses: Vec<_> = transactions.iter().map(|t| t.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                TransactionStatus::Confirmed,
+                TransactionStatus::Pending,
+                TransactionStatus::Refunded,
+                TransactionStatus::Unknown
+            ]
+        );
+
+        let mut confirmed = transactions.clone();
+        Pipeline::default()
+            .with(Filter::Status {
+                include_pending: false,
+            })
+            .run(&mut confirmed);
+        let ids: Vec<_> = confirmed.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["1"]);
+
+        Pipeline::default()
+            .with(Filter::Status {
+                include_pending: true,
+            })
+            .run(&mut transactions);
+        let statuses: Vec<_> = transactions.iter().map(|t| t.status).collect();
+        assert_eq!(
+            statuses,
+            vec![TransactionStatus::Confirmed, TransactionStatus::Pending]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_amounts_in_cents_or_reais() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            { "id": "1", "createdAt": "2023-06-01T12:00:00.000Z", "amount": 123456 },
+            { "id": "2", "createdAt": "2023-06-02T12:00:00.000Z", "amount": "123456" },
+            { "id": "3", "createdAt": "2023-06-03T12:00:00.000Z", "amount": "1234,56" },
+            { "id": "4", "createdAt": "2023-06-04T12:00:00.000Z", "amount": "1.234,56" },
+            { "id": "5", "createdAt": "2023-06-05T12:00:00.000Z", "amount": "1,234.56" },
+            { "id": "6", "createdAt": "2023-06-06T12:00:00.000Z", "amount": "1.234,5" },
+            { "id": "7", "createdAt": "2023-06-07T12:00:00.000Z" }
+        ]"#;
+
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default(),
+        );
+        let amounts: Vec<_> = transactions.iter().map(|t| t.amount_cents).collect();
+        assert_eq!(
+            amounts,
+            vec![-123456, -123456, -123456, -123456, -123456, -123450, 0]
+        );
+
+        let invalid = r#"[{ "createdAt": "2023-06-01T12:00:00.000Z", "amount": "R$ 10" }]"#;
+        assert!(serde_json::from_str::<Vec<StatementItem>>(invalid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn derive_ids_by_strategy() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            { "id": "txn-1", "createdAt": "2023-06-01T12:00:00.000Z", "amount": 100, "status": "PENDING" },
+            { "createdAt": "2023-06-02T12:00:00.000Z", "amount": 100 },
+            { "id": "", "createdAt": "2023-06-03T12:00:00.000Z", "amount": 100 }
+        ]"#;
+        let ids = |strategy| -> Result<Vec<String>, anyhow::Error> {
+            Ok(statement_to_transactions(
+                serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+                &ConversionOptions::default().with_id_strategy(strategy),
+            )
+            .into_iter()
+            .map(|t| t.id)
+            .collect())
+        };
+
+        assert_eq!(ids(IdStrategy::Provider)?, vec!["txn-1", "", ""]);
+
+        let hashes = ids(IdStrategy::ContentHash)?;
+        assert!(hashes
+            .iter()
+            .all(|id| id.len() == 16 && id.chars().all(|c: char| c.is_ascii_hexdigit())));
+        assert_ne!(hashes[1], hashes[2]);
+        assert_eq!(hashes, ids(IdStrategy::ContentHash)?);
+        // Confirming a pending item keeps its hash
+        let confirmed = fixture.replace("PENDING", "CONFIRMED");
+        let confirmed = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(&confirmed)?,
+            &ConversionOptions::default().with_id_strategy(IdStrategy::ContentHash),
+        );
+        assert_eq!(confirmed[0].id, hashes[0]);
+
+        assert_eq!(
+            ids(IdStrategy::ProviderOrHash)?,
+            vec!["txn-1", hashes[1].as_str(), hashes[2].as_str()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn map_actions_to_transaction_types() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            { "id": "1", "createdAt": "2023-06-01T12:00:00.000Z", "action": "DEBIT", "amount": 100 },
+            { "id": "2", "createdAt": "2023-06-02T12:00:00.000Z", "action": "CREDIT", "amount": 100 },
+            { "id": "3", "createdAt": "2023-06-03T12:00:00.000Z", "action": "REVERSAL", "amount": 100 },
+            { "id": "4", "createdAt": "2023-06-04T12:00:00.000Z", "action": "FEE", "amount": 100 },
+            { "id": "5", "createdAt": "2023-06-05T12:00:00.000Z", "action": "ADJUSTMENT", "amount": -100 },
+            { "id": "6", "createdAt": "2023-06-06T12:00:00.000Z", "action": "CASHBACK", "amount": 100 },
+            { "id": "7", "createdAt": "2023-06-07T12:00:00.000Z", "amount": 100 }
+        ]"#;
+
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default(),
+        );
+        let types: Vec<_> = transactions
+            .iter()
+            .map(|t| (t.type_.as_str(), t.amount_cents))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("DEBIT", -100),
+                ("CREDIT", 100),
+                ("CREDIT", 100),
+                ("FEE", -100),
+                ("OTHER", -100),
+                ("DEBIT", -100),
+                ("DEBIT", -100),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn order_same_second_transactions_by_id() -> Result<(), anyhow::Error> {
+        let fixture = r#"[
+            { "id": "b", "createdAt": "2023-06-01T12:00:00.000Z" },
+            { "id": "c", "createdAt": "2023-06-01T12:00:00.000Z" },
+            { "id": "z", "createdAt": "2023-05-31T12:00:00.000Z" },
+            { "id": "a", "createdAt": "2023-06-01T12:00:00.000Z" }
+        ]"#;
+
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default(),
+        );
+        let ids: Vec<_> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["z", "a", "b", "c"]);
+
+        let transactions = statement_to_transactions(
+            serde_json::from_str::<Vec<StatementItem>>(fixture)?,
+            &ConversionOptions::default().with_preserve_order(true),
+        );
+        let ids: Vec<_> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "c", "z", "a"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn time_out_hung_requests() -> Result<(), anyhow::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        tokio::spawn(async move {
+            // Accept connections but never answer them
+            let mut connections = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+
+        let client = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(format!("http://{}", address))
+            .timeout(Duration::from_millis(200))
+            .build()?;
+
+        let error = client
+            .get_statement(StatementQuery::default())
+            .await
+            .expect_err("request should time out");
+        assert!(error.to_string().contains("timed out"), "{}", error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_spares_slow_responses() -> Result<(), anyhow::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        tokio::spawn(async move {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            let _ = read_request(&mut socket).await;
+            // Connected right away, but slower to answer than the connect timeout
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            let body = r#"{ "hasNext": false, "items": [] }"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+
+        let client = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(format!("http://{}", address))
+            .timeout(Duration::from_secs(5))
+            .connect_timeout(Duration::from_millis(100))
+            .build()?;
+        let response = client.get_statement(StatementQuery::default()).await?;
+        assert!(!response.has_next);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_cuts_short_unanswered_connects() -> Result<(), anyhow::Error> {
+        // Never accepts, so once its backlog is full further connects go unanswered
+        let socket = TcpSocket::new_v4()?;
+        socket.bind("127.0.0.1:0".parse()?)?;
+        let address = socket.local_addr()?;
+        let _listener = socket.listen(1)?;
+        let mut backlog = vec![];
+        for _ in 0..4 {
+            let connect = TcpStream::connect(address);
+            if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(100), connect).await
+            {
+                backlog.push(stream);
+            }
+        }
+
+        let client = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(format!("http://{}", address))
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_millis(200))
+            .build()?;
+        let started = std::time::Instant::now();
+        let error = client
+            .get_statement(StatementQuery::default())
+            .await
+            .expect_err("connecting should time out");
+
+        assert!(started.elapsed() < Duration::from_secs(5), "{:#}", error);
+
+        Ok(())
+    }
+
+    async fn read_request(socket: &mut TcpStream) -> std::io::Result<String> {
+        let mut request = vec![];
+        let mut buffer = [0; 4096];
+        loop {
+            let read = socket.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+
+            let text = String::from_utf8_lossy(&request);
+            if let Some(head_end) = text.find("\r\n\r\n") {
+                let content_length = text[..head_end]
+                    .lines()
+                    .find_map(|line| {
+                        line.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .and_then(|length| length.trim().parse::<usize>().ok())
+                    })
+                    .unwrap_or(0);
+                if request.len() >= head_end + 4 + content_length {
+                    break;
+                }
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&request).into_owned())
+    }
+
+    /// Answers each incoming request with the next of `responses`, closing the connection
+    /// afterwards. Returns the url to reach it and the requests it received.
+    async fn mock_server(
+        responses: Vec<(u16, &'static str)>,
+    ) -> Result<(String, Arc<Mutex<Vec<String>>>), anyhow::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let Ok(request) = read_request(&mut socket).await else {
+                    return;
+                };
+                received.lock().unwrap().push(request);
+
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+
+        Ok((format!("http://{}", address), requests))
+    }
+
+    #[tokio::test]
+    async fn refresh_once_on_unauthorized() -> Result<(), anyhow::Error> {
+        let (base_url, requests) = mock_server(vec![
+            (200, r#"{ "bearerToken": "first" }"#),
+            (401, "{}"),
+            (200, r#"{ "bearerToken": "second" }"#),
+            (200, r#"{ "hasNext": false, "items": [] }"#),
+        ])
+        .await?;
+
+        let mut client = CajuClient::new(base_url, "user".to_string(), "employee".to_string())?;
+        client.login("expired", "refresh").await?;
+        client.get_statement(StatementQuery::default()).await?;
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[3]
+            .to_ascii_lowercase()
+            .contains("authorization: bearer second"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn build_client_with_custom_endpoints() -> Result<(), anyhow::Error> {
+        let (base_url, requests) = mock_server(vec![
+            (200, r#"{ "bearerToken": "first" }"#),
+            (200, r#"{ "hasNext": false, "items": [] }"#),
+        ])
+        .await?;
+
+        let mut client = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(base_url)
+            .http_client(reqwest::Client::new())
+            .timeout(Duration::from_secs(5))
+            .user_agent("importer-test".to_string())
+            .correlation_id("run-1".to_string())
+            .endpoints(CajuEndpoints {
+                bearer_token: "/v2/users/{user_id}/token".to_string(),
+                statement: "/v2/employees/{employee_id}/statement".to_string(),
+            })
+            .build()?;
+        client.login("expired", "refresh").await?;
+        client.get_statement(StatementQuery::default()).await?;
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("POST /v2/users/user/token "));
+        assert!(requests[1].starts_with("GET /v2/employees/employee/statement?"));
+        for request in requests.iter() {
+            let request = request.to_ascii_lowercase();
+            assert!(request.contains("user-agent: importer-test"));
+            assert!(request.contains("x-correlation-id: run-1"));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_dates_in_request_format() -> Result<(), anyhow::Error> {
+        let (base_url, requests) = mock_server(vec![
+            (200, r#"{ "bearerToken": "first" }"#),
+            (200, r#"{ "hasNext": false, "items": [] }"#),
+            (200, r#"{ "bearerToken": "second" }"#),
+            (200, r#"{ "hasNext": false, "items": [] }"#),
+        ])
+        .await?;
+        let june = Some((
+            NaiveDate::from_ymd_opt(2023, 6, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2023, 6, 30).unwrap(),
+        ));
+
+        let mut client = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(base_url.clone())
+            .build()?;
+        client.login("expired", "refresh").await?;
+        client
+            .get_statement(StatementQuery::default().with_date_range(june))
+            .await?;
+
+        let mut custom = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(base_url)
+            .date_format("%d/%m/%Y".to_string())
+            .build()?;
+        custom.login("first", "refresh").await?;
+        custom
+            .get_statement(StatementQuery::default().with_date_range(june))
+            .await?;
+
+        let requests = requests.lock().unwrap();
+        assert!(
+            requests[1].contains("&start_date=2023-06-01&end_date=2023-06-30 "),
+            "{}",
+            requests[1]
+        );
+        assert!(
+            requests[3].contains("&start_date=01%2F06%2F2023&end_date=30%2F06%2F2023 "),
+            "{}",
+            requests[3]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn give_up_when_refresh_does_not_help() -> Result<(), anyhow::Error> {
+        let (base_url, requests) = mock_server(vec![
+            (200, r#"{ "bearerToken": "first" }"#),
+            (401, "{}"),
+            (200, r#"{ "bearerToken": "first" }"#),
+            (401, "{}"),
+            (200, r#"{ "hasNext": false, "items": [] }"#),
+        ])
+        .await?;
+
+        let mut client = CajuClient::builder("user".to_string(), "employee".to_string())
+            .base_url(base_url)
+            .timeout(Duration::from_secs(5))
+            .build()?;
+        client.login("expired", "refresh").await?;
+        let error = client
+            .get_statement(StatementQuery::default())
+            .await
+            .expect_err("statement should stay unauthorized");
+
+        assert!(
+            format!("{:#}", error).contains("appears expired"),
+            "{:#}",
+            error
+        );
+        assert_eq!(requests.lock().unwrap().len(), 4);
+
+        Ok(())
     }
 }
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::currency::{format_amount, STATEMENT_CURRENCY};
+use crate::transaction::Transaction;
+
+/// How a merchant's spend changed from the base period to the other one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// Only spent at in the other period.
+    New,
+    /// Only spent at in the base period.
+    Gone,
+    Changed,
+    Unchanged,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct MerchantDelta {
+    pub merchant: String,
+    pub base_cents: i64,
+    pub other_cents: i64,
+    pub delta_cents: i64,
+    pub change: Change,
+}
+
+/// Spend of two periods side by side. Spend is the sum of the money that left the account, as a
+/// positive amount, so credits such as top-ups and refunds are not counted.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Comparison {
+    pub base_cents: i64,
+    pub other_cents: i64,
+    pub delta_cents: i64,
+    /// Sorted by the size of the change, largest first.
+    pub merchants: Vec<MerchantDelta>,
+}
+
+/// Compares the spend of `other` to the one of `base`. Merchants are matched on their exact
+/// payee, so the transactions should be converted with normalized payees for "iFood" and "IFOOD"
+/// to count as the same merchant.
+pub fn compare(base: &[Transaction], other: &[Transaction]) -> Comparison {
+    let mut spend: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+    for transaction in base.iter().filter(|t| t.amount_cents < 0) {
+        spend.entry(&transaction.payee).or_default().0 -= transaction.amount_cents;
+    }
+    for transaction in other.iter().filter(|t| t.amount_cents < 0) {
+        spend.entry(&transaction.payee).or_default().1 -= transaction.amount_cents;
+    }
+
+    let mut merchants: Vec<MerchantDelta> = spend
+        .into_iter()
+        .map(|(merchant, (base_cents, other_cents))| MerchantDelta {
+            merchant: merchant.to_string(),
+            base_cents,
+            other_cents,
+            delta_cents: other_cents - base_cents,
+            change: match (base_cents, other_cents) {
+                (0, _) => Change::New,
+                (_, 0) => Change::Gone,
+                (base, other) if base == other => Change::Unchanged,
+                _ => Change::Changed,
+            },
+        })
+        .collect();
+    // Stable, so merchants with the same change stay in name order
+    merchants.sort_by_key(|m| std::cmp::Reverse(m.delta_cents.abs()));
+
+    let base_cents = merchants.iter().map(|m| m.base_cents).sum();
+    let other_cents = merchants.iter().map(|m| m.other_cents).sum();
+    Comparison {
+        base_cents,
+        other_cents,
+        delta_cents: other_cents - base_cents,
+        merchants,
+    }
+}
+
+impl Comparison {
+    /// Renders the comparison as a plain text table with a column per period, headed by
+    /// `base_label` and `other_label`.
+    pub fn to_table(&self, base_label: &str, other_label: &str) -> String {
+        let mut rows = vec![[
+            "Merchant".to_string(),
+            base_label.to_string(),
+            other_label.to_string(),
+            "Delta".to_string(),
+            String::new(),
+        ]];
+        for merchant in &self.merchants {
+            rows.push([
+                merchant.merchant.clone(),
+                format_amount(merchant.base_cents, STATEMENT_CURRENCY),
+                format_amount(merchant.other_cents, STATEMENT_CURRENCY),
+                format_delta(merchant.delta_cents),
+                match merchant.change {
+                    Change::New => "new",
+                    Change::Gone => "gone",
+                    Change::Changed | Change::Unchanged => "",
+                }
+                .to_string(),
+            ]);
+        }
+        rows.push([
+            "Total".to_string(),
+            format_amount(self.base_cents, STATEMENT_CURRENCY),
+            format_amount(self.other_cents, STATEMENT_CURRENCY),
+            format_delta(self.delta_cents),
+            String::new(),
+        ]);
+
+        let mut widths = [0; 5];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let mut table = String::new();
+        for row in &rows {
+            let mut line = format!("{:<width$}", row[0], width = widths[0]);
+            for (cell, width) in row[1..4].iter().zip(&widths[1..4]) {
+                line.push_str(&format!("  {:>width$}", cell, width = width));
+            }
+            line.push_str(&format!("  {}", row[4]));
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
+
+fn format_delta(cents: i64) -> String {
+    let sign = if cents > 0 { "+" } else { "" };
+    format!("{}{}", sign, format_amount(cents, STATEMENT_CURRENCY))
+}
+
+#[cfg(test)]
+mod test {
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::compare::{compare, Change};
+    use crate::transaction::{Transaction, TransactionStatus};
+
+    fn transaction(month: u32, payee: &str, amount_cents: i64) -> Transaction {
+        Transaction {
+            id: format!("{}-{}", month, payee),
+            date: Utc.with_ymd_and_hms(2023, month, 10, 12, 0, 0).unwrap(),
+            initiated_at: None,
+            amount_cents,
+            type_: "DEBIT".to_string(),
+            category_code: None,
+            payee: payee.to_string(),
+            memo: payee.to_string(),
+            status: TransactionStatus::Confirmed,
+        }
+    }
+
+    #[test]
+    fn compare_two_months() -> Result<(), anyhow::Error> {
+        let may = vec![
+            transaction(5, "IFOOD", -3000),
+            transaction(5, "IFOOD", -2000),
+            transaction(5, "PADARIA", -1500),
+            transaction(5, "CINEMA", -4000),
+            // Top-ups are not spend
+            transaction(5, "CAJU", 50000),
+        ];
+        let june = vec![
+            transaction(6, "IFOOD", -7000),
+            transaction(6, "PADARIA", -1500),
+            transaction(6, "RAPPI", -2500),
+        ];
+
+        let comparison = compare(&may, &june);
+
+        assert_eq!(comparison.base_cents, 10500);
+        assert_eq!(comparison.other_cents, 11000);
+        assert_eq!(comparison.delta_cents, 500);
+        let merchants: Vec<_> = comparison
+            .merchants
+            .iter()
+            .map(|m| (m.merchant.as_str(), m.delta_cents, m.change))
+            .collect();
+        assert_eq!(
+            merchants,
+            vec![
+                ("CINEMA", -4000, Change::Gone),
+                ("RAPPI", 2500, Change::New),
+                ("IFOOD", 2000, Change::Changed),
+                ("PADARIA", 0, Change::Unchanged),
+            ]
+        );
+
+        assert_eq!(
+            comparison.to_table("2023-05", "2023-06"),
+            concat!(
+                "Merchant  2023-05  2023-06   Delta\n",
+                "CINEMA      40.00     0.00  -40.00  gone\n",
+                "RAPPI        0.00    25.00  +25.00  new\n",
+                "IFOOD       50.00    70.00  +20.00\n",
+                "PADARIA     15.00    15.00    0.00\n",
+                "Total      105.00   110.00   +5.00\n",
+            )
+        );
+
+        let json = serde_json::to_value(&comparison)?;
+        assert_eq!(json["delta_cents"], 500);
+        assert_eq!(json["merchants"][1]["change"], "new");
+
+        Ok(())
+    }
+}
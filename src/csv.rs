@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+
+use crate::transaction::Transaction;
+
+/// How numbers and dates are written in CSV output.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum Locale {
+    /// 1234.56 and 2023-06-30, separated by commas.
+    #[default]
+    Iso,
+    /// 1.234,56 and 30/06/2023, separated by semicolons, as Brazilian spreadsheets expect.
+    Br,
+}
+
+impl Locale {
+    fn separator(self) -> char {
+        match self {
+            Locale::Iso => ',',
+            Locale::Br => ';',
+        }
+    }
+
+    fn format_date(self, date: NaiveDate) -> String {
+        match self {
+            Locale::Iso => date.format("%Y-%m-%d").to_string(),
+            Locale::Br => date.format("%d/%m/%Y").to_string(),
+        }
+    }
+
+    fn format_amount(self, cents: i64) -> String {
+        let sign = if cents < 0 { "-" } else { "" };
+        let units = (cents.unsigned_abs() / 100).to_string();
+        let fraction = cents.unsigned_abs() % 100;
+        match self {
+            Locale::Iso => format!("{}{}.{:02}", sign, units, fraction),
+            Locale::Br => {
+                let mut grouped = String::new();
+                for (i, digit) in units.chars().enumerate() {
+                    if i > 0 && (units.len() - i).is_multiple_of(3) {
+                        grouped.push('.');
+                    }
+                    grouped.push(digit);
+                }
+                format!("{}{},{:02}", sign, grouped, fraction)
+            }
+        }
+    }
+}
+
+/// Renders transactions as CSV with a header row. Fields are quoted when they contain the
+/// separator, quotes or line breaks.
+pub fn to_csv(transactions: &[Transaction], locale: Locale) -> String {
+    let separator = locale.separator();
+    let quote = |field: &str| {
+        if field.contains([separator, '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    };
+
+    let mut output = ["date", "payee", "memo", "amount", "type", "id"].join(&separator.to_string());
+    output.push('\n');
+    for transaction in transactions {
+        let row = [
+            locale.format_date(transaction.local_date().date_naive()),
+            quote(&transaction.payee),
+            quote(&transaction.memo),
+            quote(&locale.format_amount(transaction.amount_cents)),
+            quote(&transaction.type_),
+            quote(&transaction.id),
+        ];
+        output.push_str(&row.join(&separator.to_string()));
+        output.push('\n');
+    }
+    output
+}
+
+/// Renders the number of transactions and their total for each day, in the statement timezone,
+/// as CSV with a header row. Days without transactions are left out.
+pub fn to_day_summary(transactions: &[Transaction], locale: Locale) -> String {
+    let mut days: BTreeMap<NaiveDate, (usize, i64)> = BTreeMap::new();
+    for transaction in transactions {
+        let day = days
+            .entry(transaction.local_date().date_naive())
+            .or_default();
+        day.0 += 1;
+        day.1 += transaction.amount_cents;
+    }
+
+    let separator = locale.separator().to_string();
+    let mut output = ["date", "count", "total"].join(&separator);
+    output.push('\n');
+    for (date, (count, total)) in days {
+        let row = [
+            locale.format_date(date),
+            count.to_string(),
+            locale.format_amount(total),
+        ];
+        output.push_str(&row.join(&separator));
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::csv::{to_csv, to_day_summary, Locale};
+    use crate::transaction::{Transaction, TransactionStatus};
+
+    fn transaction(amount_cents: i64, payee: &str) -> Transaction {
+        Transaction {
+            id: "abc".to_string(),
+            // Late at night in UTC is still the previous day in BRT
+            date: Utc.with_ymd_and_hms(2023, 7, 1, 2, 0, 0).unwrap(),
+            initiated_at: None,
+            amount_cents,
+            type_: "DEBIT".to_string(),
+            category_code: None,
+            payee: payee.to_string(),
+            memo: payee.to_string(),
+            status: TransactionStatus::Confirmed,
+        }
+    }
+
+    #[test]
+    fn write_in_each_locale() {
+        let transactions = vec![
+            transaction(-123456, "Mercado, Centro"),
+            transaction(1234567890, "Depósito"),
+        ];
+
+        assert_eq!(
+            to_csv(&transactions, Locale::Iso),
+            "date,payee,memo,amount,type,id\n\
+            2023-06-30,\"Mercado, Centro\",\"Mercado, Centro\",-1234.56,DEBIT,abc\n\
+            2023-06-30,Depósito,Depósito,12345678.90,DEBIT,abc\n"
+        );
+        assert_eq!(
+            to_csv(&transactions, Locale::Br),
+            "date;payee;memo;amount;type;id\n\
+            30/06/2023;Mercado, Centro;Mercado, Centro;-1.234,56;DEBIT;abc\n\
+            30/06/2023;Depósito;Depósito;12.345.678,90;DEBIT;abc\n"
+        );
+    }
+
+    #[test]
+    fn sum_transactions_per_day() {
+        let mut transactions = vec![
+            transaction(-1050, "Padaria"),
+            transaction(-2000, "Mercado"),
+            transaction(5000, "Depósito"),
+        ];
+        // 12:00 UTC on July 1st, the next day in BRT
+        transactions[2].date = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            to_day_summary(&transactions, Locale::Iso),
+            "date,count,total\n2023-06-30,2,-30.50\n2023-07-01,1,50.00\n"
+        );
+        assert_eq!(
+            to_day_summary(&transactions, Locale::Br),
+            "date;count;total\n30/06/2023;2;-30,50\n01/07/2023;1;50,00\n"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+/// Currency every statement is reported in.
+pub const STATEMENT_CURRENCY: &str = "BRL";
+
+/// Number of decimal places of an ISO 4217 currency. Amounts are always kept in the currency's
+/// minor unit (cents for BRL), so this is what turns them back into decimals.
+pub fn minor_units(currency_code: &str) -> u32 {
+    match currency_code {
+        "CLP" | "JPY" | "KRW" | "PYG" => 0,
+        "BHD" | "KWD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// Formats an amount in minor units as a plain decimal, e.g. -1234 BRL as "-12.34".
+pub fn format_amount(amount: i64, currency_code: &str) -> String {
+    let places = minor_units(currency_code);
+    let sign = if amount < 0 { "-" } else { "" };
+    let divisor = 10u64.pow(places);
+    let units = amount.unsigned_abs() / divisor;
+    if places == 0 {
+        return format!("{}{}", sign, units);
+    }
+    let fraction = amount.unsigned_abs() % divisor;
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        units,
+        fraction,
+        width = places as usize
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::currency::format_amount;
+
+    #[test]
+    fn format_by_minor_units() {
+        assert_eq!(format_amount(-1234, "BRL"), "-12.34");
+        assert_eq!(format_amount(5, "BRL"), "0.05");
+        assert_eq!(format_amount(-1234, "JPY"), "-1234");
+        assert_eq!(format_amount(1234, "KWD"), "1.234");
+    }
+
+    #[test]
+    fn format_edge_amounts() {
+        assert_eq!(format_amount(0, "BRL"), "0.00");
+        assert_eq!(format_amount(-7, "BRL"), "-0.07");
+        assert_eq!(format_amount(123456789012345, "BRL"), "1234567890123.45");
+        assert_eq!(format_amount(i64::MIN, "BRL"), "-92233720368547758.08");
+        assert_eq!(format_amount(0, "JPY"), "0");
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// Kind of failure, attached as context to errors so that the process exits with a code scripts
+/// can tell apart. See the exit code table in the README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Caju rejected the tokens.
+    Auth,
+    /// The request could not be sent or Caju answered with an error status.
+    Network,
+    /// There were no transactions to convert.
+    Empty,
+    /// The arguments or environment are invalid.
+    Config,
+    /// The output could not be written.
+    Output,
+    /// More transactions than allowed were skipped.
+    Skipped,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Auth => 2,
+            Failure::Network => 3,
+            Failure::Empty => 4,
+            Failure::Config => 5,
+            Failure::Output => 6,
+            Failure::Skipped => 7,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Failure::Auth => "authentication failed",
+            Failure::Network => "request to Caju failed",
+            Failure::Empty => "no transactions",
+            Failure::Config => "invalid configuration",
+            Failure::Output => "failed to write output",
+            Failure::Skipped => "too many transactions skipped",
+        };
+        f.write_str(description)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exit code for an error, taken from the outermost [`Failure`] in its chain. Untagged HTTP
+/// errors count as network failures and anything else exits with 1.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    if let Some(failure) = error.downcast_ref::<Failure>() {
+        failure.exit_code()
+    } else if error.chain().any(|e| e.is::<reqwest::Error>()) {
+        Failure::Network.exit_code()
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use anyhow::Context;
+
+    use crate::error::{exit_code, Failure};
+
+    #[test]
+    fn map_failures_to_exit_codes() {
+        let untagged = anyhow::anyhow!("boom");
+        assert_eq!(exit_code(&untagged), 1);
+
+        let auth = anyhow::anyhow!("401 Unauthorized").context(Failure::Auth);
+        assert_eq!(exit_code(&auth), 2);
+
+        let nested: anyhow::Result<()> = Err(anyhow::anyhow!("disk full"))
+            .context(Failure::Output)
+            .context("Writing caju_202306.ofx");
+        assert_eq!(exit_code(&nested.unwrap_err()), 6);
+
+        let config = anyhow::Error::new(Failure::Config);
+        assert_eq!(exit_code(&config), 5);
+    }
+}
@@ -0,0 +1,337 @@
+use std::fmt;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc, Weekday};
+use regex::Regex;
+
+use crate::transaction::{Transaction, TransactionStatus};
+
+/// A single stage of a [`Pipeline`].
+pub enum Filter {
+    /// Keeps confirmed transactions, and pending ones if asked to.
+    Status { include_pending: bool },
+    /// Keeps transactions with one of these statuses.
+    Statuses(Vec<TransactionStatus>),
+    /// Keeps transactions posted on one of these weekdays.
+    Weekdays(Vec<Weekday>),
+    /// Keeps transactions posted between these days, inclusive.
+    Period { start: NaiveDate, end: NaiveDate },
+    /// Drops scheduled transactions dated after `now`.
+    NotFuture { now: DateTime<Utc> },
+    /// Keeps transactions posted at or after `since` and before `until`, when set.
+    Between {
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    },
+    /// Keeps transactions whose payee matches any of these patterns.
+    IncludeMerchants(Vec<Regex>),
+    /// Drops transactions whose payee matches any of these patterns.
+    ExcludeMerchants(Vec<Regex>),
+}
+
+impl Filter {
+    fn name(&self) -> &'static str {
+        match self {
+            Filter::Status { .. } | Filter::Statuses(_) => "status",
+            Filter::Weekdays(_) => "weekday",
+            Filter::Period { .. } => "period",
+            Filter::NotFuture { .. } => "future",
+            Filter::Between { .. } => "range",
+            Filter::IncludeMerchants(_) => "include-merchant",
+            Filter::ExcludeMerchants(_) => "exclude-merchant",
+        }
+    }
+
+    fn keep(&self, transaction: &Transaction) -> bool {
+        match self {
+            Filter::Status { include_pending } => match transaction.status {
+                TransactionStatus::Confirmed => true,
+                TransactionStatus::Pending => *include_pending,
+                TransactionStatus::Refunded | TransactionStatus::Unknown => false,
+            },
+            Filter::Statuses(statuses) => statuses.contains(&transaction.status),
+            Filter::Weekdays(weekdays) => weekdays.contains(&transaction.local_date().weekday()),
+            Filter::Period { start, end } => {
+                (*start..=*end).contains(&transaction.local_date().date_naive())
+            }
+            Filter::NotFuture { now } => transaction.date <= *now,
+            Filter::Between { since, until } => {
+                !matches!(since, Some(since) if transaction.date < *since)
+                    && !matches!(until, Some(until) if transaction.date >= *until)
+            }
+            Filter::IncludeMerchants(patterns) => {
+                patterns.iter().any(|p| p.is_match(&transaction.payee))
+            }
+            Filter::ExcludeMerchants(patterns) => {
+                !patterns.iter().any(|p| p.is_match(&transaction.payee))
+            }
+        }
+    }
+}
+
+/// Filters applied one after the other, keeping track of how many transactions each removed.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Filter>,
+}
+
+impl Pipeline {
+    pub fn with(mut self, filter: Filter) -> Self {
+        self.stages.push(filter);
+        self
+    }
+
+    pub fn run(&self, transactions: &mut Vec<Transaction>) -> Breakdown {
+        let fetched = transactions.len();
+        let removed = self
+            .stages
+            .iter()
+            .map(|stage| {
+                let before = transactions.len();
+                transactions.retain(|t| stage.keep(t));
+                (stage.name(), before - transactions.len())
+            })
+            .collect();
+
+        Breakdown {
+            fetched,
+            removed,
+            emitted: transactions.len(),
+        }
+    }
+}
+
+/// How many transactions each stage of a [`Pipeline`] removed.
+#[derive(Debug)]
+pub struct Breakdown {
+    pub fetched: usize,
+    pub removed: Vec<(&'static str, usize)>,
+    pub emitted: usize,
+}
+
+impl Breakdown {
+    /// How many transactions the stage with this name removed.
+    pub fn removed_by(&self, stage: &str) -> usize {
+        self.removed
+            .iter()
+            .filter(|(name, _)| *name == stage)
+            .map(|(_, removed)| removed)
+            .sum()
+    }
+}
+
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fetched {}", self.fetched)?;
+        for (stage, removed) in &self.removed {
+            write!(f, ", {} -{}", stage, removed)?;
+        }
+        write!(f, " => {} emitted", self.emitted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use chrono::{Duration, NaiveDate, TimeZone, Utc, Weekday};
+    use regex::Regex;
+
+    use crate::filter::{Filter, Pipeline};
+    use crate::transaction::{Transaction, TransactionStatus};
+
+    fn transaction_at(year: i32, month: u32, day: u32, hour: u32) -> Transaction {
+        Transaction {
+            id: format!("{}-{}-{}T{}", year, month, day, hour),
+            date: Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap(),
+            initiated_at: None,
+            amount_cents: -100,
+            type_: "DEBIT".to_string(),
+            category_code: None,
+            payee: "iFood".to_string(),
+            memo: "iFood".to_string(),
+            status: TransactionStatus::Confirmed,
+        }
+    }
+
+    #[test]
+    fn filter_weekdays_in_statement_timezone() {
+        let mut transactions = vec![
+            // Saturday
+            transaction_at(2023, 6, 3, 15),
+            // Sunday 01:00 UTC, still Saturday in BRT
+            transaction_at(2023, 6, 4, 1),
+            // Monday
+            transaction_at(2023, 6, 5, 15),
+        ];
+
+        let breakdown = Pipeline::default()
+            .with(Filter::Weekdays(vec![Weekday::Sat]))
+            .run(&mut transactions);
+
+        assert_eq!(breakdown.removed, vec![("weekday", 1)]);
+        assert_eq!(transactions.len(), 2);
+    }
+
+    #[test]
+    fn trim_padded_days_to_period() {
+        let mut transactions = vec![
+            // Padding before the month
+            transaction_at(2023, 5, 30, 15),
+            // 01:00 UTC on the 1st is still May 31st in BRT
+            transaction_at(2023, 6, 1, 1),
+            transaction_at(2023, 6, 1, 15),
+            // 02:00 UTC on July 1st posted on June 30th in BRT
+            transaction_at(2023, 7, 1, 2),
+            // Padding after the month
+            transaction_at(2023, 7, 2, 15),
+        ];
+
+        Pipeline::default()
+            .with(Filter::Period {
+                start: NaiveDate::from_ymd_opt(2023, 6, 1).unwrap(),
+                end: NaiveDate::from_ymd_opt(2023, 6, 30).unwrap(),
+            })
+            .run(&mut transactions);
+
+        let ids: Vec<_> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["2023-6-1T15", "2023-7-1T2"]);
+    }
+
+    #[test]
+    fn drop_future_transactions() {
+        let now = Utc::now();
+        let mut tomorrow = transaction_at(2023, 6, 1, 12);
+        tomorrow.date = now + Duration::days(1);
+        let mut transactions = vec![transaction_at(2023, 6, 1, 12), tomorrow];
+
+        let breakdown = Pipeline::default()
+            .with(Filter::NotFuture { now })
+            .run(&mut transactions);
+
+        assert_eq!(breakdown.removed_by("future"), 1);
+        assert_eq!(transactions.len(), 1);
+        assert!(transactions[0].date < now);
+    }
+
+    #[test]
+    fn keep_transactions_between_datetimes() {
+        let mut transactions = vec![
+            transaction_at(2023, 6, 1, 11),
+            transaction_at(2023, 6, 1, 15),
+            transaction_at(2023, 6, 1, 18),
+            transaction_at(2023, 6, 1, 21),
+        ];
+
+        // The afternoon of June 1st in BRT
+        let breakdown = Pipeline::default()
+            .with(Filter::Between {
+                since: Some(Utc.with_ymd_and_hms(2023, 6, 1, 15, 0, 0).unwrap()),
+                until: Some(Utc.with_ymd_and_hms(2023, 6, 1, 21, 0, 0).unwrap()),
+            })
+            .run(&mut transactions);
+
+        assert_eq!(breakdown.removed_by("range"), 2);
+        let ids: Vec<_> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["2023-6-1T15", "2023-6-1T18"]);
+
+        let mut open_ended = vec![transaction_at(2023, 6, 1, 11)];
+        Pipeline::default()
+            .with(Filter::Between {
+                since: None,
+                until: None,
+            })
+            .run(&mut open_ended);
+        assert_eq!(open_ended.len(), 1);
+    }
+
+    #[test]
+    fn include_then_exclude_merchants() -> Result<(), regex::Error> {
+        let with_payee = |payee: &str| {
+            let mut transaction = transaction_at(2023, 6, 1, 12);
+            transaction.payee = payee.to_string();
+            transaction
+        };
+        let mut transactions = vec![
+            with_payee("iFood"),
+            with_payee("IFD*IFOOD"),
+            with_payee("Rappi"),
+            with_payee("Rappi Turbo"),
+            with_payee("Padaria"),
+        ];
+
+        let breakdown = Pipeline::default()
+            .with(Filter::IncludeMerchants(vec![
+                Regex::new("(?i)ifood")?,
+                Regex::new("^Rappi")?,
+            ]))
+            .with(Filter::ExcludeMerchants(vec![Regex::new("Turbo$")?]))
+            .run(&mut transactions);
+
+        assert_eq!(
+            breakdown.to_string(),
+            "fetched 5, include-merchant -1, exclude-merchant -1 => 3 emitted"
+        );
+        let payees: Vec<_> = transactions.iter().map(|t| t.payee.as_str()).collect();
+        assert_eq!(payees, vec!["iFood", "IFD*IFOOD", "Rappi"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn select_explicit_statuses() {
+        let with_status = |status| {
+            let mut transaction = transaction_at(2023, 6, 1, 12);
+            transaction.status = status;
+            transaction
+        };
+        let transactions = vec![
+            with_status(TransactionStatus::Confirmed),
+            with_status(TransactionStatus::Pending),
+            with_status(TransactionStatus::Refunded),
+            with_status(TransactionStatus::Unknown),
+        ];
+
+        let mut refunded = transactions.clone();
+        Pipeline::default()
+            .with(Filter::Statuses(vec![TransactionStatus::Refunded]))
+            .run(&mut refunded);
+        let statuses: Vec<_> = refunded.iter().map(|t| t.status).collect();
+        assert_eq!(statuses, vec![TransactionStatus::Refunded]);
+
+        let mut selected = transactions;
+        Pipeline::default()
+            .with(Filter::Statuses(vec![
+                TransactionStatus::Confirmed,
+                TransactionStatus::Refunded,
+            ]))
+            .run(&mut selected);
+        let statuses: Vec<_> = selected.iter().map(|t| t.status).collect();
+        assert_eq!(
+            statuses,
+            vec![TransactionStatus::Confirmed, TransactionStatus::Refunded]
+        );
+    }
+
+    #[test]
+    fn count_removed_per_stage() {
+        let mut pending = transaction_at(2023, 6, 3, 15);
+        pending.status = TransactionStatus::Pending;
+        let mut transactions = vec![
+            pending,
+            transaction_at(2023, 6, 3, 16),
+            transaction_at(2023, 6, 5, 15),
+            transaction_at(2023, 6, 6, 15),
+        ];
+
+        let breakdown = Pipeline::default()
+            .with(Filter::Status {
+                include_pending: false,
+            })
+            .with(Filter::Weekdays(vec![Weekday::Sat, Weekday::Mon]))
+            .run(&mut transactions);
+
+        assert_eq!(
+            breakdown.to_string(),
+            "fetched 4, status -1, weekday -1 => 2 emitted"
+        );
+    }
+}
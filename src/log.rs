@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// Set by --quiet. Warnings and progress messages are dropped while errors still go to stderr.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Set at startup, so that messages can be matched to the requests of the run.
+static CORRELATION_ID: OnceLock<String> = OnceLock::new();
+
+pub fn set_correlation_id(correlation_id: String) {
+    let _ = CORRELATION_ID.set(correlation_id);
+}
+
+pub fn correlation_id() -> Option<&'static str> {
+    CORRELATION_ID.get().map(String::as_str)
+}
+
+/// Prints a warning or progress message to stderr, unless running with --quiet. Prefixed with the
+/// correlation id when one is set.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::log::is_quiet() {
+            match $crate::log::correlation_id() {
+                Some(id) => eprintln!("[{}] {}", id, format_args!($($arg)*)),
+                None => eprintln!($($arg)*),
+            }
+        }
+    };
+}
+
+pub(crate) use info;
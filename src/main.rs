@@ -1,17 +1,76 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
 
-use chrono::Datelike;
-use clap::Parser;
+use anyhow::Context;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use regex::Regex;
 use secrecy::{Secret, ExposeSecret};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::caju::CajuClient;
+use crate::caju::{CajuClient, ConversionOptions, IdStrategy, PayeeSource};
+use crate::csv::Locale;
+use crate::error::Failure;
+use crate::filter::{Filter, Pipeline};
+use crate::transaction::{SignConvention, TransactionStatus, STATEMENT_TIMEZONE};
 use crate::ofx::Ofx;
 
 mod caju;
+mod compare;
+mod csv;
+mod currency;
+mod error;
+mod filter;
+mod log;
 mod ofx;
+mod refund;
+mod transaction;
+mod transfer;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// OFX credit card statement, ready to be imported into Actual.
+    Ofx,
+    /// JSON array of transactions, handy for post-processing with jq.
+    Json,
+    /// CSV with one transaction per line, formatted according to --locale.
+    Csv,
+    /// CSV with the number of transactions and their total for each day, formatted according to
+    /// --locale.
+    DaySummary,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Ofx => "ofx",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::DaySummary => "days.csv",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Ofx => "application/x-ofx",
+            Format::Json => "application/json",
+            Format::Csv | Format::DaySummary => "text/csv",
+        }
+    }
+}
 
 #[derive(Parser)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct App {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(long = "base-url", env = "BASE_URL", default_value = "https://apigw.caju.com.br")]
     // Base url of the Caju API.
     base_url: String,
@@ -19,78 +78,1215 @@ struct App {
     #[arg(long = "bearer-token", env = "BEARER_TOKEN")]
     /// Bearer token for the Caju API. Can be obtained from a MITM proxy when opening the Caju
     /// mobile app.
-    bearer_token: Secret<String>,
+    bearer_token: Option<Secret<String>>,
+
+    #[arg(long = "bearer-token-file", env = "BEARER_TOKEN_FILE")]
+    /// File containing the bearer token, such as a Docker secret. Used instead of --bearer-token.
+    bearer_token_file: Option<PathBuf>,
 
     #[arg(long = "refresh-token", env = "REFRESH_TOKEN")]
     /// Refresh token for the Caju API. Can be obtained from a MITM proxy when opening the Caju
     /// mobile app.
-    refresh_token: Secret<String>,
+    refresh_token: Option<Secret<String>>,
 
-    #[arg(long = "user-id", env = "USER_ID")]
+    #[arg(long = "refresh-token-file", env = "REFRESH_TOKEN_FILE")]
+    /// File containing the refresh token, such as a Docker secret. Used instead of
+    /// --refresh-token.
+    refresh_token_file: Option<PathBuf>,
+
+    #[arg(long = "print-token")]
+    /// Print the bearer token obtained when logging in to stderr, so it can be reused as
+    /// --bearer-token.
+    print_token: bool,
+
+    #[arg(
+        long = "user-id",
+        env = "USER_ID",
+        required_unless_present = "show_config"
+    )]
     // User id of your caju user. Can be obtained from a MITM proxy when opening the Caju app.
-    user_id: String,
+    user_id: Option<String>,
 
-    #[arg(long = "employee-id", env = "EMPLOYEE_ID")]
+    #[arg(
+        long = "employee-id",
+        env = "EMPLOYEE_ID",
+        required_unless_present = "show_config"
+    )]
     // Employee id of your caju account. Can be obtained from a MITM proxy when opening the Caju app.
-    employee_id: String,
+    employee_id: Option<String>,
 
-    /// Month to get statement for. Accepts numbers or english month names.
-    month: String,
+    #[arg(required_unless_present = "show_config")]
+    /// Month to get statement for. Accepts numbers, english month names, english and portuguese
+    /// abbreviations such as "dec" or "dez", portuguese month names, or "this-month",
+    /// "last-month" and "N-months-ago", which also pick the year.
+    month: Option<String>,
 
     /// Year to get statement for. Default is current year according to local timezone.
     year: Option<i32>,
 
+    #[arg(long = "cycle-day", value_parser = clap::value_parser!(u32).range(1..=28))]
+    /// Day the benefit resets on. The statement then runs from this day of the previous month up
+    /// to the day before it in the given month, instead of the calendar month.
+    cycle_day: Option<u32>,
+
+    #[arg(long = "pad-days", default_value_t = 0)]
+    /// Also fetch this many days before and after the statement period, keeping the transactions
+    /// that were posted within the period but that the API files under neighbouring days.
+    pad_days: u32,
+
     #[arg(short = 'o', long = "output")]
-    /// The file name to output OFX to. Default is stdout.
+    /// The file name to output to. Default is stdout.
     filename: Option<String>,
+
+    #[arg(long = "output-dir", conflicts_with = "filename")]
+    /// Directory to write the output to, in a file named after the provider and period, e.g.
+    /// caju_202306.ofx. The directory is created if needed.
+    output_dir: Option<PathBuf>,
+
+    #[arg(long = "post-url")]
+    /// POST the output to this URL, e.g. an import service, instead of printing it to stdout. A
+    /// file is still written when --output or --output-dir are given.
+    post_url: Option<reqwest::Url>,
+
+    #[arg(long = "post-header", requires = "post_url", value_parser = parse_header)]
+    /// Extra header for --post-url, as "Name: value". Can be repeated.
+    post_headers: Vec<(String, String)>,
+
+    #[arg(long = "post-process")]
+    /// Shell command to pipe the output through before it is written or posted, e.g. a script
+    /// that categorizes transactions. Its stdout becomes the output and a failure stops the run.
+    post_process: Option<String>,
+
+    #[arg(long = "bom")]
+    /// Start the written output, file or stdout, with a UTF-8 byte order mark. Some Windows
+    /// importers misread accented characters without one.
+    bom: bool,
+
+    #[arg(long = "split-by-sign")]
+    /// Write debits and credits to separate files, named e.g. caju_debits_202306.ofx and
+    /// caju_credits_202306.ofx, each covering only its own transactions. Requires --output-dir.
+    split_by_sign: bool,
+
+    #[arg(long = "split-monthly")]
+    /// Write a file per calendar month (in BRT) when the statement spans several, as with
+    /// --cycle-day or --pad-days, e.g. caju_202305.ofx and caju_202306.ofx. Each covers only its
+    /// own month. Requires --output-dir.
+    split_monthly: bool,
+
+    #[arg(long = "merge")]
+    /// Merge into the existing OFX output file instead of overwriting it. Transactions already
+    /// in the file are kept, and the ones fetched now replace those with the same FITID.
+    merge: bool,
+
+    #[arg(long = "bank-id", default_value = "Caju")]
+    /// Value of the OFX BANKID. Some importers reject non-numeric bank ids, so a numeric code
+    /// they accept can be given here.
+    bank_id: String,
+
+    #[arg(long = "account-id-map")]
+    /// JSON file mapping "<provider>:<employee id>" or just "<provider>" to the OFX ACCTID, e.g.
+    /// {"caju:1234": "caju-alice"}, to route each statement to its own account. ACCTID is left
+    /// out when nothing matches.
+    account_id_map: Option<PathBuf>,
+
+    #[arg(long = "emit-dtacctup")]
+    /// Write the time of the run as the OFX DTACCTUP, for importers that show when the account was
+    /// last updated.
+    emit_dtacctup: bool,
+
+    #[arg(long = "tag-provider")]
+    /// Prefix each MEMO with the provider the transaction came from, e.g. "[Caju] iFood", to see
+    /// which card it was made with when several providers share an account.
+    tag_provider: bool,
+
+    #[arg(long = "fitid-prefix", default_value = "caju")]
+    /// Tag prepended to every FITID as "<prefix>:<id>", keeping ids unique when several providers
+    /// are imported into the same account. Pass an empty string to disable it.
+    fitid_prefix: String,
+
+    #[arg(long = "transactions-only", conflicts_with = "merge")]
+    /// Only output the BANKTRANLIST with the STMTTRN records, leaving out the OFX header, SIGNON
+    /// and account blocks. The result is not a complete OFX file on its own, it's meant to be
+    /// wrapped by another tool.
+    transactions_only: bool,
+
+    #[arg(long = "minimal", conflicts_with = "transactions_only")]
+    /// Only write the OFX elements strict importers require, leaving out MEMO, DTUSER, SIC and
+    /// the balances.
+    minimal: bool,
+
+    #[arg(short = 'f', long = "format", value_enum, default_values_t = [Format::Ofx])]
+    /// Output format. Can be repeated to write several formats from the same fetch, which
+    /// requires --output-dir.
+    formats: Vec<Format>,
+
+    #[arg(long = "locale", value_enum, default_value_t = Locale::Iso)]
+    /// Number and date format of CSV output. OFX always follows the spec.
+    locale: Locale,
+
+    #[arg(long = "sign-convention", value_enum, default_value_t = SignConvention::Asset)]
+    /// Sign given to amounts, to match the type of the account in Actual. Applies to every
+    /// output format.
+    sign_convention: SignConvention,
+
+    #[arg(long = "payee-from", value_enum, default_value_t = PayeeSource::Auto)]
+    /// Which field becomes the payee (OFX NAME). When a specific field is chosen, the other ones
+    /// are kept in MEMO.
+    payee_from: PayeeSource,
+
+    #[arg(long = "id-strategy", value_enum, default_value_t = IdStrategy::Provider)]
+    /// How transaction ids (OFX FITID) are derived. Changing it changes the ids of transactions
+    /// already imported, so the importer will see them as new.
+    id_strategy: IdStrategy,
+
+    #[arg(long = "no-trim")]
+    /// Keep payees and memos as Caju sends them. By default leading and trailing whitespace is
+    /// removed and repeated whitespace is collapsed into a single space.
+    no_trim: bool,
+
+    #[arg(long = "normalize-payees")]
+    /// Uppercase payee names (OFX NAME) and strip their accents, so that "iFood" and "IFOOD" end
+    /// up as the same payee. The original description is kept in MEMO.
+    normalize_payees: bool,
+
+    #[arg(long = "compact-memo")]
+    /// Write MEMO as the operation type, Caju id and merchant category code of the transaction,
+    /// e.g. "op=PIX | ref=1234 | mcc=5812", skipping the ones Caju doesn't send.
+    compact_memo: bool,
+
+    #[arg(long = "include-raw-id")]
+    /// Append the Caju id of each transaction to MEMO, e.g. "Padaria [id:abc123]", to find it
+    /// back in the app. Importers usually hide FITID, which holds the same id.
+    include_raw_id: bool,
+
+    #[arg(long = "preserve-order")]
+    /// Keep transactions in the order Caju returned them instead of sorting them by date, to
+    /// compare with the app. Importers usually expect STMTTRN records in date order, and Caju
+    /// doesn't always return items sharing the same second in the same order. --merge sorts
+    /// anyway.
+    preserve_order: bool,
+
+    #[arg(long = "include-pending")]
+    /// Also include transactions that are still pending. By default only confirmed ones are
+    /// included.
+    include_pending: bool,
+
+    #[arg(
+        long = "only-status",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with = "include_pending"
+    )]
+    /// Only keep transactions with these statuses, e.g. "confirmed,refunded". Replaces the
+    /// default of keeping confirmed transactions only.
+    only_statuses: Vec<TransactionStatus>,
+
+    #[arg(long = "since", value_parser = parse_since)]
+    /// Only keep transactions posted at or after this day (YYYY-MM-DD, in BRT) or RFC 3339
+    /// datetime, e.g. 2023-06-01T12:00:00-03:00. The statement is still fetched for the month.
+    since: Option<DateTime<Utc>>,
+
+    #[arg(long = "until", value_parser = parse_until)]
+    /// Only keep transactions posted up to the end of this day (YYYY-MM-DD, in BRT) or before this
+    /// RFC 3339 datetime.
+    until: Option<DateTime<Utc>>,
+
+    #[arg(long = "allow-future")]
+    /// Keep transactions dated after now, such as scheduled ones. By default they're dropped, as
+    /// they haven't happened yet.
+    allow_future: bool,
+
+    #[arg(long = "net-refunds")]
+    /// Link refunds to the purchases they revert, keeping both with a shared reference in MEMO so
+    /// that they net out. Refunds without a matching purchase are dropped as usual.
+    net_refunds: bool,
+
+    #[arg(long = "drop-internal-transfers")]
+    /// Drop transfers between the card's own purses, e.g. from food to flexible balance. Both legs
+    /// are dropped, found as a debit and a credit of the same amount at the same instant.
+    drop_internal_transfers: bool,
+
+    #[arg(long = "only-weekday", value_parser = parse_weekday)]
+    /// Only keep transactions posted on this weekday (mon..sun). Can be repeated.
+    only_weekdays: Vec<chrono::Weekday>,
+
+    #[arg(long = "include-merchant", value_parser = parse_regex)]
+    /// Only keep transactions whose payee matches this regex, e.g. "(?i)ifood". Can be repeated to
+    /// keep payees matching any of them. Applied before --exclude-merchant.
+    include_merchants: Vec<Regex>,
+
+    #[arg(long = "exclude-merchant", value_parser = parse_regex)]
+    /// Drop transactions whose payee matches this regex. Can be repeated.
+    exclude_merchants: Vec<Regex>,
+
+    #[arg(long = "max-memo-len", default_value_t = 255)]
+    /// Truncate payees and memos longer than this many characters, as some importers reject long
+    /// OFX fields. 0 disables truncation.
+    max_memo_len: usize,
+
+    #[arg(long = "annotate-balance")]
+    /// Append the running balance after each transaction to its MEMO, e.g. "iFood | saldo:
+    /// 123,45". Not standard OFX, meant for reconciling by hand.
+    annotate_balance: bool,
+
+    #[arg(
+        long = "opening-balance",
+        requires = "annotate_balance",
+        value_parser = parse_cents,
+        default_value = "0"
+    )]
+    /// Balance before the first transaction, used by --annotate-balance. Accepts "1234,56" or
+    /// "1234.56".
+    opening_balance: i64,
+
+    #[arg(short = 'v', long = "verbose")]
+    /// Print how many transactions each filter removed.
+    verbose: bool,
+
+    #[arg(long = "dry-run")]
+    /// Fetch and convert the statement, but neither write nor post it. With --verbose, the
+    /// transactions that would have been written are previewed on stderr.
+    dry_run: bool,
+
+    #[arg(long = "preview-limit", default_value_t = 20)]
+    /// How many transactions --dry-run --verbose previews. 0 shows them all.
+    preview_limit: usize,
+
+    #[arg(short = 'q', long = "quiet", conflicts_with = "verbose")]
+    /// Only print errors, leaving out warnings and progress messages. The output is written as
+    /// usual. Can't be combined with --verbose.
+    quiet: bool,
+
+    #[arg(
+        long = "http-timeout",
+        visible_alias = "read-timeout",
+        default_value_t = 30
+    )]
+    /// Timeout for each request to the Caju API and --post-url, in seconds, from connecting until
+    /// the whole response is read.
+    http_timeout: u64,
+
+    #[arg(long = "correlation-id")]
+    /// Id sent in the x-correlation-id header of every request, to tie together the requests of a
+    /// run in proxy logs. A random UUID by default. Progress messages are prefixed with it.
+    correlation_id: Option<String>,
+
+    #[arg(long = "connect-timeout", default_value_t = 10)]
+    /// Timeout for connecting to the Caju API and --post-url, in seconds. Lets an unreachable
+    /// server fail fast without cutting short slow responses.
+    connect_timeout: u64,
+
+    #[arg(long = "max-pages", default_value_t = 1000)]
+    /// Maximum number of statement pages to fetch. Guards against the API paginating forever.
+    max_pages: usize,
+
+    #[arg(long = "max-skipped", default_value_t = 0)]
+    /// Exit with an error, after writing the output, when more than this many transactions were
+    /// skipped because Caju sent something unexpected, which usually means its API changed. 0
+    /// allows any number.
+    max_skipped: usize,
+
+    #[arg(long = "strict")]
+    /// Fail instead of working around inconsistencies in the statement. Without it, transactions
+    /// sharing an id get a -2, -3, ... suffix so that importers don't merge them.
+    strict: bool,
+
+    #[arg(long = "show-config")]
+    /// Print the effective configuration and where each value came from, with secrets redacted,
+    /// then exit without calling the Caju API.
+    show_config: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a .env file setting every environment variable the importer reads, with the secrets
+    /// left blank, ready to be filled in.
+    ConfigInit {
+        #[arg(long = "path", default_value = ".env")]
+        /// File to write.
+        path: PathBuf,
+
+        #[arg(long = "force")]
+        /// Overwrite the file if it already exists.
+        force: bool,
+    },
+
+    /// Compare the spend of two months, in total and per merchant. Credentials are read from the
+    /// environment.
+    Compare {
+        /// Month to compare against, e.g. "may", "5" or "2-months-ago".
+        base: String,
+
+        /// Month to compare, e.g. "june" or "last-month".
+        other: String,
+
+        #[arg(long = "format", value_enum, default_value_t = CompareFormat::Table)]
+        /// How to print the comparison.
+        format: CompareFormat,
+    },
+
+    /// Find how far back Caju keeps statements, walking back month by month from the current one
+    /// until several months in a row are empty. Credentials are read from the environment.
+    ProbeRange {
+        #[arg(long = "max-lookback-months", default_value_t = 36)]
+        /// Stop after going back this many months.
+        max_lookback_months: u32,
+
+        #[arg(long = "empty-months", default_value_t = 3)]
+        /// Stop after this many empty months in a row, older than the latest month with data.
+        empty_months: u32,
+    },
+
+    /// Print the OFX elements the importer writes, with their nesting and which ones are optional,
+    /// for configuring strict importers.
+    #[command(hide = true)]
+    DumpSchema,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum CompareFormat {
+    /// Aligned columns, for reading in a terminal.
+    Table,
+    /// The comparison with amounts in cents, for scripts.
+    Json,
+}
+
+/// Arguments whose values are never printed.
+const SECRET_ARGS: [&str; 2] = ["bearer_token", "refresh_token"];
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv()?;
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
 
-    let app = App::parse();
+async fn run() -> anyhow::Result<()> {
+    let preset_vars: HashSet<OsString> = std::env::vars_os().map(|(name, _)| name).collect();
+    match dotenvy::dotenv() {
+        // config-init is what creates it
+        Err(e) if e.not_found() => {}
+        result => {
+            result.context(Failure::Config)?;
+        }
+    }
 
-    let month = try_into_month(&app.month).unwrap_or_else(|_| {
-        chrono::Month::try_from(chrono::Local::now().month() as u8)
-            .expect("month from Local::now() should be valid")
-    });
-    let year = app.year.unwrap_or_else(|| chrono::Local::now().year());
+    let matches = App::command()
+        .try_get_matches()
+        .unwrap_or_else(|e| exit_on_usage_error(e));
+    let app = App::from_arg_matches(&matches).unwrap_or_else(|e| exit_on_usage_error(e));
+    log::set_quiet(app.quiet);
+    let correlation_id = app
+        .correlation_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    log::set_correlation_id(correlation_id.clone());
 
-    let mut client = CajuClient::new(app.base_url, app.user_id, app.employee_id)?;
-    client.login(app.bearer_token.expose_secret(), app.refresh_token.expose_secret()).await?;
+    if let Some(Command::ConfigInit { path, force }) = &app.command {
+        config_init(path, *force)?;
+        log::info!("Wrote {}", path.display());
+        return Ok(());
+    }
+    if let Some(Command::DumpSchema) = &app.command {
+        print!("{}", ofx::SCHEMA.to_tree());
+        return Ok(());
+    }
+    if let Some(Command::Compare { .. }) = &app.command {
+        return compare_months(app, correlation_id).await;
+    }
+    if let Some(Command::ProbeRange { .. }) = &app.command {
+        return probe_range(app, correlation_id).await;
+    }
+    // Before anything is required, since it's how a misconfigured env gets debugged
+    if app.show_config {
+        for line in describe_config(&matches, &preset_vars) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    // clap requires these unless a subcommand or --show-config is given
+    let (Some(user_id), Some(employee_id), Some(month_arg)) = (
+        app.user_id.clone(),
+        app.employee_id.clone(),
+        app.month.clone(),
+    ) else {
+        anyhow::bail!("Missing --user-id, --employee-id or month");
+    };
+
+    let mut formats: Vec<Format> = vec![];
+    for format in &app.formats {
+        if !formats.contains(format) {
+            formats.push(*format);
+        }
+    }
+    validate(&app, &formats).context(Failure::Config)?;
+
+    let bearer_token = resolve_secret(
+        "bearer-token",
+        "BEARER_TOKEN",
+        app.bearer_token,
+        app.bearer_token_file.as_deref(),
+    )
+    .context(Failure::Config)?;
+    let refresh_token = resolve_secret(
+        "refresh-token",
+        "REFRESH_TOKEN",
+        app.refresh_token,
+        app.refresh_token_file.as_deref(),
+    )
+    .context(Failure::Config)?;
+    let account_ids = match &app.account_id_map {
+        Some(path) => load_account_ids(path).context(Failure::Config)?,
+        None => HashMap::new(),
+    };
+
+    let today = chrono::Utc::now()
+        .with_timezone(&STATEMENT_TIMEZONE)
+        .date_naive();
+    let (month, year) = match relative_month(&month_arg, today) {
+        Some(_) if app.year.is_some() => {
+            return Err(anyhow::anyhow!(
+                "{} already sets the year, drop the year argument",
+                month_arg
+            )
+            .context(Failure::Config));
+        }
+        Some(relative) => relative,
+        None => {
+            let month = try_into_month(&month_arg).unwrap_or_else(|_| {
+                chrono::Month::try_from(today.month() as u8)
+                    .expect("month from today's date should be valid")
+            });
+            let year = app.year.unwrap_or_else(|| today.year());
+            (month, year)
+        }
+    };
+    if month.number_from_month() == today.month() && year == today.year() {
+        log::info!(
+            "Warning: {}/{} is the current month, the statement is incomplete and its totals \
+            won't match the final one",
+            month.name(),
+            year
+        );
+    }
+
+    let mut client = CajuClient::builder(user_id, employee_id.clone())
+        .base_url(app.base_url)
+        .timeout(std::time::Duration::from_secs(app.http_timeout))
+        .connect_timeout(std::time::Duration::from_secs(app.connect_timeout))
+        .correlation_id(correlation_id.clone())
+        .build()?;
+    let login = client
+        .login(bearer_token.expose_secret(), refresh_token.expose_secret())
+        .await?;
+    if app.print_token {
+        eprintln!(
+            "Warning: the token below grants access to your Caju account, keep it secret. \
+            It is short lived and only useful until it expires."
+        );
+        eprintln!("BEARER_TOKEN={}", login.bearer_token());
+    }
     let client = client;
 
-    let statement = client.get_month_statement(Some(year), month).await?;
+    let statement = client
+        .get_month_statement_result(
+            Some(year),
+            month,
+            app.cycle_day,
+            app.pad_days,
+            app.max_pages,
+        )
+        .await?;
+    if statement.truncated {
+        log::info!(
+            "Warning: stopped paginating after {} pages, the statement may be incomplete",
+            statement.page_count
+        );
+    }
 
-    let ofx: Ofx = match statement.try_into() {
-        Ok(i) => i,
-        Err(e) => {
-            eprintln!("Error for {}/{}: {}", month.name(), year, e);
-            return Err(e);
+    let options = ConversionOptions::default()
+        .with_normalize_payees(app.normalize_payees)
+        .with_payee_from(app.payee_from)
+        .with_trim_whitespace(!app.no_trim)
+        .with_compact_memo(app.compact_memo)
+        .with_include_raw_id(app.include_raw_id)
+        .with_preserve_order(app.preserve_order)
+        .with_id_strategy(app.id_strategy);
+
+    let period = if app.pad_days > 0 {
+        // Padded transactions outside the period are trimmed below
+        caju::requested_period(statement.start_date, statement.end_date)
+    } else {
+        caju::statement_period(
+            &statement.items,
+            Some((statement.start_date, statement.end_date)),
+        )
+    };
+    let skipped = caju::count_skipped(&statement.items);
+    let mut transactions = caju::statement_to_transactions(statement.items, &options);
+
+    if app.net_refunds {
+        let linked = refund::link_refunds(&mut transactions, chrono::Duration::days(30));
+        if app.verbose {
+            eprintln!("Linked {} refunds to their purchases", linked);
+        }
+    }
+    if app.drop_internal_transfers {
+        let dropped = transfer::drop_internal_transfers(&mut transactions);
+        if dropped > 0 {
+            log::info!("Dropped {} internal transfers between purses", dropped);
+        }
+    }
+
+    let mut pipeline = Pipeline::default().with(if app.only_statuses.is_empty() {
+        Filter::Status {
+            include_pending: app.include_pending,
+        }
+    } else {
+        Filter::Statuses(app.only_statuses.clone())
+    });
+    if !app.allow_future {
+        pipeline = pipeline.with(Filter::NotFuture {
+            now: chrono::Utc::now(),
+        });
+    }
+    if app.pad_days > 0 {
+        pipeline = pipeline.with(Filter::Period {
+            start: statement.start_date,
+            end: statement.end_date,
+        });
+    }
+    if app.since.is_some() || app.until.is_some() {
+        pipeline = pipeline.with(Filter::Between {
+            since: app.since,
+            until: app.until,
+        });
+    }
+    if !app.only_weekdays.is_empty() {
+        pipeline = pipeline.with(Filter::Weekdays(app.only_weekdays.clone()));
+    }
+    if !app.include_merchants.is_empty() {
+        pipeline = pipeline.with(Filter::IncludeMerchants(app.include_merchants.clone()));
+    }
+    if !app.exclude_merchants.is_empty() {
+        pipeline = pipeline.with(Filter::ExcludeMerchants(app.exclude_merchants.clone()));
+    }
+
+    let breakdown = pipeline.run(&mut transactions);
+    if app.verbose {
+        eprintln!("{}", breakdown);
+    }
+    let future = breakdown.removed_by("future");
+    if future > 0 {
+        log::info!(
+            "Dropped {} transactions dated in the future, pass --allow-future to keep them",
+            future
+        );
+    }
+    if !app.include_merchants.is_empty() || !app.exclude_merchants.is_empty() {
+        log::info!(
+            "Kept {} transactions, {} from other payees and {} from excluded payees dropped",
+            breakdown.emitted,
+            breakdown.removed_by("include-merchant"),
+            breakdown.removed_by("exclude-merchant")
+        );
+    }
+
+    let duplicates = transaction::duplicate_ids(&transactions);
+    if !duplicates.is_empty() && app.strict {
+        anyhow::bail!("Transactions share the ids {}", duplicates.join(", "));
+    }
+    let disambiguated = transaction::disambiguate_ids(&mut transactions);
+    if disambiguated > 0 {
+        log::info!(
+            "Warning: suffixed the ids of {} transactions that repeated an earlier one",
+            disambiguated
+        );
+    }
+
+    if app.tag_provider {
+        transaction::tag_provider(&mut transactions, "Caju");
+    }
+    if app.annotate_balance {
+        transaction::annotate_balances(&mut transactions, app.opening_balance);
+    }
+
+    let truncated = transaction::truncate_descriptions(&mut transactions, app.max_memo_len);
+    if truncated > 0 {
+        log::info!(
+            "Truncated the description of {} transactions to {} characters",
+            truncated,
+            app.max_memo_len
+        );
+    }
+
+    if app.dry_run {
+        if app.verbose {
+            app.sign_convention.apply(&mut transactions);
+            eprint!(
+                "{}",
+                transaction::preview_table(&transactions, app.preview_limit)
+            );
         }
+        log::info!(
+            "Dry run, {} transactions were not written",
+            transactions.len()
+        );
+        return check_skipped(skipped, app.max_skipped).context(Failure::Skipped);
+    }
+
+    let client = match app.post_url {
+        Some(_) => Some(
+            reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(app.http_timeout))
+                .connect_timeout(std::time::Duration::from_secs(app.connect_timeout))
+                .build()?,
+        ),
+        None => None,
+    };
+
+    let parts = if app.split_by_sign {
+        let (debits, credits) = transaction::split_by_sign(transactions);
+        vec![(Some("debits"), debits), (Some("credits"), credits)]
+    } else {
+        vec![(None, transactions)]
+    };
+    let mut split = vec![];
+    for (part, transactions) in parts {
+        if app.split_monthly {
+            for (part_month, transactions) in transaction::split_by_month(transactions) {
+                split.push((part, Some(part_month), transactions));
+            }
+        } else {
+            split.push((part, None, transactions));
+        }
+    }
+
+    for (part, part_month, mut transactions) in split {
+        let (year, month) = match part_month {
+            Some((part_year, part_month)) => {
+                let part_month = chrono::Month::try_from(part_month as u8)
+                    .expect("month of a date should be valid");
+                (part_year, part_month)
+            }
+            None => (year, month),
+        };
+        app.sign_convention.apply(&mut transactions);
+        let provider = match part {
+            Some(part) => format!("caju_{}", part),
+            None => "caju".to_string(),
+        };
+
+        for &format in &formats {
+            let output_path = match (&app.filename, &app.output_dir) {
+                (Some(filename), _) => Some(PathBuf::from(filename)),
+                (None, Some(dir)) => {
+                    std::fs::create_dir_all(dir).context(Failure::Output)?;
+                    let path = dir.join(output_file_name(&provider, year, month, format));
+                    if path.exists() && !(app.merge && format == Format::Ofx) {
+                        log::info!("Warning: overwriting {}", path.display());
+                    }
+                    Some(path)
+                }
+                (None, None) => None,
+            };
+
+            let output = match format {
+                Format::Ofx => {
+                    let bounds =
+                        part_month.and_then(|(year, month)| transaction::month_bounds(year, month));
+                    let range = transaction::date_range(&transactions);
+                    let period = match (bounds, part, range, &period) {
+                        (Some((start, end)), _, _, Ok(period)) => {
+                            (start.max(period.0), end.min(period.1))
+                        }
+                        (Some(bounds), _, _, Err(_)) => bounds,
+                        (None, Some(_), Some(range), _) => range,
+                        (None, _, _, Ok(period)) => *period,
+                        (None, _, _, Err(e)) => {
+                            eprintln!("Error for {}/{}: {:#}", month.name(), year, e);
+                            // The period can only be missing when there are no transactions
+                            return Err(anyhow::anyhow!("{:#}", e).context(Failure::Empty));
+                        }
+                    };
+                    let mut ofx = caju::transactions_to_ofx(period, &transactions)
+                        .with_bank_id(&app.bank_id)
+                        .with_fitid_prefix(&app.fitid_prefix);
+                    if let Some(account_id) = account_id(&account_ids, "caju", &employee_id) {
+                        ofx = ofx.with_account_id(account_id);
+                    }
+                    if app.emit_dtacctup {
+                        ofx = ofx.with_account_updated(&chrono::Utc::now());
+                    }
+                    if let (true, Some(path)) = (app.merge, output_path.as_ref()) {
+                        if path.exists() {
+                            ofx.merge(Ofx::from_ofx(&std::fs::read_to_string(path)?)?)?;
+                        }
+                    }
+                    if app.transactions_only {
+                        ofx.to_transaction_list()
+                    } else if app.minimal {
+                        ofx.to_minimal_ofx()?
+                    } else {
+                        ofx.to_ofx()?
+                    }
+                }
+                Format::Json => serde_json::to_string_pretty(&transactions)?,
+                Format::Csv => csv::to_csv(&transactions, app.locale),
+                Format::DaySummary => csv::to_day_summary(&transactions, app.locale),
+            };
+            let output = match &app.post_process {
+                Some(command) => post_process(command, output)?,
+                None => output,
+            };
+
+            let bytes = output_bytes(&output, app.bom);
+            match (&output_path, &app.post_url) {
+                (Some(path), _) => write_atomically(path, &bytes).context(Failure::Output)?,
+                (None, Some(_)) => {}
+                (None, None) => std::io::stdout()
+                    .write_all(&bytes)
+                    .context(Failure::Output)?,
+            }
+
+            if let (Some(url), Some(client)) = (&app.post_url, &client) {
+                let mut request = client
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, format.content_type())
+                    .header("x-correlation-id", &correlation_id);
+                for (name, value) in &app.post_headers {
+                    request = request.header(name, value);
+                }
+                let response = request.body(output).send().await?;
+                log::info!(
+                    "Posted {} to {}: {}",
+                    format.extension(),
+                    url,
+                    response.status()
+                );
+                response.error_for_status()?;
+            }
+
+            // Only the output itself goes to stdout, so that it can be piped
+            if let Some(path) = &output_path {
+                log::info!(
+                    "Wrote {} for {}/{} at {}",
+                    format.extension(),
+                    month.name(),
+                    year,
+                    path.display()
+                );
+            }
+        }
+    }
+
+    check_skipped(skipped, app.max_skipped).context(Failure::Skipped)
+}
+
+/// Fetches the two months of the compare subcommand and prints how their spend changed. Payees
+/// are normalized so that merchants match regardless of case, accents and spacing.
+async fn compare_months(mut app: App, correlation_id: String) -> anyhow::Result<()> {
+    let Some(Command::Compare {
+        base,
+        other,
+        format,
+    }) = app.command.take()
+    else {
+        anyhow::bail!("Not a compare command");
+    };
+    let today = chrono::Utc::now()
+        .with_timezone(&STATEMENT_TIMEZONE)
+        .date_naive();
+    let (base_month, base_year) = resolve_month(&base, today).context(Failure::Config)?;
+    let (other_month, other_year) = resolve_month(&other, today).context(Failure::Config)?;
+    let max_pages = app.max_pages;
+    let client = connect(app, correlation_id).await?;
+
+    let options = ConversionOptions::default()
+        .with_trim_whitespace(true)
+        .with_normalize_payees(true);
+    let mut periods = vec![];
+    for (month, year) in [(base_month, base_year), (other_month, other_year)] {
+        let items = client
+            .get_month_statement(Some(year), month, max_pages)
+            .await?;
+        let mut transactions = caju::statement_to_transactions(items, &options);
+        Pipeline::default()
+            .with(Filter::Status {
+                include_pending: false,
+            })
+            .run(&mut transactions);
+        periods.push(transactions);
+    }
+
+    let comparison = compare::compare(&periods[0], &periods[1]);
+    match format {
+        CompareFormat::Table => print!(
+            "{}",
+            comparison.to_table(
+                &format!("{}-{:02}", base_year, base_month.number_from_month()),
+                &format!("{}-{:02}", other_year, other_month.number_from_month()),
+            )
+        ),
+        CompareFormat::Json => println!("{}", serde_json::to_string_pretty(&comparison)?),
+    }
+    Ok(())
+}
+
+/// Walks back from the current month to find the latest and earliest months Caju has statements
+/// for, fetching a single item per month.
+async fn probe_range(mut app: App, correlation_id: String) -> anyhow::Result<()> {
+    let Some(Command::ProbeRange {
+        max_lookback_months,
+        empty_months,
+    }) = app.command.take()
+    else {
+        anyhow::bail!("Not a probe-range command");
+    };
+    let today = chrono::Utc::now()
+        .with_timezone(&STATEMENT_TIMEZONE)
+        .date_naive();
+    let client = connect(app, correlation_id).await?;
+
+    let month_ago = |months_ago: u32| -> anyhow::Result<(chrono::Month, i32)> {
+        relative_month(&format!("{}-months-ago", months_ago), today)
+            .ok_or(anyhow::anyhow!("Can't go back {} months", months_ago))
+    };
+    let range = caju::probe_months(max_lookback_months, empty_months, |months_ago| {
+        let client = &client;
+        async move {
+            let (month, year) = month_ago(months_ago)?;
+            log::info!("Probing {}/{}", month.name(), year);
+            client.has_month_statement(year, month).await
+        }
+    })
+    .await?;
+
+    let Some(range) = range else {
+        return Err(
+            anyhow::anyhow!("No statements in the last {} months", max_lookback_months)
+                .context(Failure::Empty),
+        );
+    };
+    let (latest_month, latest_year) = month_ago(range.latest)?;
+    let (earliest_month, earliest_year) = month_ago(range.earliest)?;
+    println!(
+        "Earliest month with data: {}-{:02}",
+        earliest_year,
+        earliest_month.number_from_month()
+    );
+    println!(
+        "Latest month with data: {}-{:02}",
+        latest_year,
+        latest_month.number_from_month()
+    );
+    if range.earliest == max_lookback_months {
+        log::info!(
+            "Reached --max-lookback-months {}, there may be older data",
+            max_lookback_months
+        );
+    }
+    Ok(())
+}
+
+/// Logs in to Caju for a subcommand, with the credentials from the environment.
+async fn connect(app: App, correlation_id: String) -> anyhow::Result<CajuClient> {
+    let (Some(user_id), Some(employee_id)) = (app.user_id, app.employee_id) else {
+        return Err(anyhow::anyhow!("USER_ID and EMPLOYEE_ID must be set").context(Failure::Config));
     };
+    let bearer_token = resolve_secret(
+        "bearer-token",
+        "BEARER_TOKEN",
+        app.bearer_token,
+        app.bearer_token_file.as_deref(),
+    )
+    .context(Failure::Config)?;
+    let refresh_token = resolve_secret(
+        "refresh-token",
+        "REFRESH_TOKEN",
+        app.refresh_token,
+        app.refresh_token_file.as_deref(),
+    )
+    .context(Failure::Config)?;
+    let mut client = CajuClient::builder(user_id, employee_id)
+        .base_url(app.base_url)
+        .timeout(std::time::Duration::from_secs(app.http_timeout))
+        .connect_timeout(std::time::Duration::from_secs(app.connect_timeout))
+        .correlation_id(correlation_id)
+        .build()?;
+    client
+        .login(bearer_token.expose_secret(), refresh_token.expose_secret())
+        .await?;
+    Ok(client)
+}
 
-    match app.filename {
-        Some(ref fname) => Box::new(
-            std::fs::OpenOptions::new()
-                .truncate(true)
-                .create(true)
-                .write(true)
-                .open(fname)?,
-        ) as Box<dyn std::io::Write>,
-        None => Box::new(std::io::stdout()) as Box<dyn std::io::Write>,
+/// Writes the .env template to `path`, refusing to replace an existing file unless `force`.
+fn config_init(path: &Path, force: bool) -> anyhow::Result<()> {
+    if path.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        )
+        .context(Failure::Config));
     }
-    .write_all(ofx.to_ofx()?.as_bytes())?;
+    write_atomically(path, env_template().as_bytes()).context(Failure::Output)
+}
 
-    if let Some(ref filename) = app.filename {
-        println!("Wrote ofx for {}/{} at {}", month.name(), year, filename);
+/// A .env file with every argument that can be set from the environment, described by its help
+/// and set to its default. Secrets are left blank, and the *_FILE alternatives commented out.
+fn env_template() -> String {
+    let mut template =
+        String::from("# Caju importer settings, see the README for how to get them\n");
+    for arg in App::command().get_arguments() {
+        let Some(env) = arg.get_env() else {
+            continue;
+        };
+        let env = env.to_string_lossy();
+        template.push('\n');
+        if let Some(help) = arg.get_help() {
+            template.push_str(&format!("# {}\n", help));
+        }
+        let default = arg
+            .get_default_values()
+            .first()
+            .map(|value| value.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let comment = if env.ends_with("_FILE") { "# " } else { "" };
+        template.push_str(&format!("{}{}={}\n", comment, env, default));
     }
+    template
+}
+
+/// Prints a clap error and exits. Usage errors exit as configuration errors instead of with clap's
+/// own code, which would be confused with an authentication failure.
+fn exit_on_usage_error(e: clap::Error) -> ! {
+    if !e.use_stderr() {
+        // --help and --version
+        e.exit();
+    }
+    let _ = e.print();
+    std::process::exit(Failure::Config.exit_code().into())
+}
 
+/// Fails when more than `max_skipped` transactions were skipped, unless it is 0.
+fn check_skipped(skipped: usize, max_skipped: usize) -> anyhow::Result<()> {
+    if max_skipped > 0 && skipped > max_skipped {
+        anyhow::bail!(
+            "Skipped {} transactions, more than --max-skipped {}",
+            skipped,
+            max_skipped
+        );
+    }
+    Ok(())
+}
+
+/// Checks the combinations of arguments that clap can't express.
+fn validate(app: &App, formats: &[Format]) -> anyhow::Result<()> {
+    if formats.len() > 1 && app.output_dir.is_none() {
+        anyhow::bail!("Writing several formats requires --output-dir");
+    }
+    if app.split_by_sign && app.output_dir.is_none() {
+        anyhow::bail!("--split-by-sign requires --output-dir");
+    }
+    if app.split_monthly && app.output_dir.is_none() {
+        anyhow::bail!("--split-monthly requires --output-dir");
+    }
+    if app.merge && !formats.contains(&Format::Ofx) {
+        anyhow::bail!("--merge is only supported for OFX output");
+    }
+    if app.transactions_only && !formats.contains(&Format::Ofx) {
+        anyhow::bail!("--transactions-only is only supported for OFX output");
+    }
+    if app.minimal && !formats.contains(&Format::Ofx) {
+        anyhow::bail!("--minimal is only supported for OFX output");
+    }
+    if app.merge && app.filename.is_none() && app.output_dir.is_none() {
+        anyhow::bail!("--merge requires --output or --output-dir");
+    }
     Ok(())
 }
 
+/// Lists every argument with its value and source, one per line. Environment variables that
+/// weren't set before loading .env are reported as coming from the .env file.
+fn describe_config(matches: &ArgMatches, preset_vars: &HashSet<OsString>) -> Vec<String> {
+    App::command()
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_id().as_str(), "help" | "version" | "show_config"))
+        .map(|arg| {
+            let id = arg.get_id().as_str();
+            let name = match arg.get_long() {
+                Some(long) => format!("--{}", long),
+                None => id.to_string(),
+            };
+            let value = match matches.get_raw(id) {
+                Some(_) if SECRET_ARGS.contains(&id) => "***".to_string(),
+                // Usually "Authorization: Bearer ...", so only the header names are shown
+                Some(values) if id == "post_headers" => values
+                    .map(|value| match value.to_string_lossy().split_once(':') {
+                        Some((name, _)) => format!("{}: ***", name.trim()),
+                        None => "***".to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                Some(values) => values
+                    .map(|value| value.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                None => "<unset>".to_string(),
+            };
+            let source = match matches.value_source(id) {
+                Some(ValueSource::CommandLine) => "flag".to_string(),
+                Some(ValueSource::EnvVariable) => match arg.get_env() {
+                    Some(env) if !preset_vars.contains(env) => {
+                        format!("file .env ({})", env.to_string_lossy())
+                    }
+                    Some(env) => format!("env ({})", env.to_string_lossy()),
+                    None => "env".to_string(),
+                },
+                Some(ValueSource::DefaultValue) => "default".to_string(),
+                _ => "unset".to_string(),
+            };
+
+            format!("{} = {} [{}]", name, value, source)
+        })
+        .collect()
+}
+
+/// Takes a secret given either directly or as a file to read it from, the way Docker and
+/// Kubernetes mount secrets. Setting both is an error.
+fn resolve_secret(
+    flag: &str,
+    env: &str,
+    direct: Option<Secret<String>>,
+    file: Option<&Path>,
+) -> anyhow::Result<Secret<String>> {
+    match (direct, file) {
+        (Some(_), Some(_)) => anyhow::bail!(
+            "Both --{flag} ({env}) and --{flag}-file ({env}_FILE) are set, only one is allowed"
+        ),
+        (Some(secret), None) => Ok(secret),
+        (None, Some(path)) => {
+            let contents = std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("Failed to read --{}-file {}: {}", flag, path.display(), e)
+            })?;
+            Ok(Secret::new(contents.trim().to_string()))
+        }
+        (None, None) => anyhow::bail!("Missing --{flag} ({env}) or --{flag}-file ({env}_FILE)"),
+    }
+}
+
+/// Reads an --account-id-map file, a JSON object of ACCTIDs keyed by provider and employee.
+fn load_account_ids(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        anyhow::anyhow!("Failed to read --account-id-map {}: {}", path.display(), e)
+    })?;
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Invalid --account-id-map {}: {}", path.display(), e))
+}
+
+/// ACCTID for an employee of a provider, falling back to the one for the whole provider.
+fn account_id<'a>(
+    account_ids: &'a HashMap<String, String>,
+    provider: &str,
+    employee_id: &str,
+) -> Option<&'a str> {
+    account_ids
+        .get(&format!("{}:{}", provider, employee_id))
+        .or_else(|| account_ids.get(provider))
+        .map(String::as_str)
+}
+
+/// The bytes to write for `output`, starting with a UTF-8 byte order mark when `bom` is set.
+fn output_bytes(output: &str, bom: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(output.len() + 3);
+    if bom {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    bytes.extend_from_slice(output.as_bytes());
+    bytes
+}
+
+/// Pipes `input` through `command`, run by the shell, and returns what it printed. Its stderr is
+/// passed through.
+fn post_process(command: &str, input: String) -> anyhow::Result<String> {
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run --post-process {}: {}", command, e))?;
+
+    // Write from another thread so that a command printing a lot can't block us both
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!("--post-process {} failed with {}", command, output.status);
+    }
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to write to --post-process {}", command))??;
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Writes to a temporary file next to `path` and then renames it over `path`, so that an
+/// interrupted run never leaves a half written file behind.
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Name of the file written in --output-dir, e.g. caju_202306.ofx.
+fn output_file_name(provider: &str, year: i32, month: chrono::Month, format: Format) -> String {
+    format!(
+        "{}_{}{:02}.{}",
+        provider,
+        year,
+        month.number_from_month(),
+        format.extension()
+    )
+}
+
+/// English and Portuguese abbreviations of each month, plus the Portuguese names, all without
+/// accents.
+const MONTH_NAMES: [&[&str]; 12] = [
+    &["jan", "janeiro"],
+    &["feb", "fev", "fevereiro"],
+    &["mar", "marco"],
+    &["apr", "abr", "abril"],
+    &["may", "mai", "maio"],
+    &["jun", "junho"],
+    &["jul", "julho"],
+    &["aug", "ago", "agosto"],
+    &["sep", "set", "setembro"],
+    &["oct", "out", "outubro"],
+    &["nov", "novembro"],
+    &["dec", "dez", "dezembro"],
+];
+
 fn try_into_month(input: &str) -> anyhow::Result<chrono::Month> {
+    let name: String = input
+        .trim()
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase();
+    let index = MONTH_NAMES
+        .iter()
+        .position(|names| names.contains(&name.as_str()));
+    if let Some(index) = index {
+        return Ok(chrono::Month::try_from(index as u8 + 1)?);
+    }
+
     let parsed = match chrono::Month::from_str(input) {
         Ok(m) => m,
         Err(_) => match input.parse::<u8>() {
@@ -102,10 +1298,119 @@ fn try_into_month(input: &str) -> anyhow::Result<chrono::Month> {
     Ok(parsed)
 }
 
+/// Resolves a month argument to a month and its year, like the statement month but failing on
+/// anything it can't parse. Month names and numbers are taken in the current year.
+fn resolve_month(input: &str, today: NaiveDate) -> anyhow::Result<(chrono::Month, i32)> {
+    if let Some(relative) = relative_month(input, today) {
+        return Ok(relative);
+    }
+    let month = try_into_month(input).map_err(|_| anyhow::anyhow!("Invalid month '{}'", input))?;
+    Ok((month, today.year()))
+}
+
+/// Resolves "this-month", "last-month" and "N-months-ago" to a month and its year, counting from
+/// `today`. Returns None for anything else.
+fn relative_month(input: &str, today: NaiveDate) -> Option<(chrono::Month, i32)> {
+    let months_ago = match input.to_lowercase().as_str() {
+        "this-month" => 0,
+        "last-month" => 1,
+        other => other.strip_suffix("-months-ago")?.parse().ok()?,
+    };
+    let date = today
+        .with_day(1)?
+        .checked_sub_months(chrono::Months::new(months_ago))?;
+    let month = chrono::Month::try_from(date.month() as u8).ok()?;
+    Some((month, date.year()))
+}
+
+/// Parses an RFC 3339 datetime, or a day starting at midnight in the statement timezone.
+fn parse_since(input: &str) -> Result<DateTime<Utc>, String> {
+    parse_instant(input, false)
+}
+
+/// Parses an RFC 3339 datetime, or a day ending at the next midnight in the statement timezone.
+fn parse_until(input: &str) -> Result<DateTime<Utc>, String> {
+    parse_instant(input, true)
+}
+
+fn parse_instant(input: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+    let invalid = || {
+        format!(
+            "invalid date '{}', expected YYYY-MM-DD or an RFC 3339 datetime",
+            input
+        )
+    };
+    let mut date = NaiveDate::parse_from_str(input, "%Y-%m-%d").map_err(|_| invalid())?;
+    if end_of_day {
+        date = date.succ_opt().ok_or_else(invalid)?;
+    }
+    transaction::start_of_day(date).ok_or_else(invalid)
+}
+
+fn parse_regex(input: &str) -> Result<Regex, String> {
+    Regex::new(input).map_err(|e| format!("invalid regex '{}': {}", input, e))
+}
+
+fn parse_weekday(input: &str) -> Result<chrono::Weekday, String> {
+    input
+        .parse()
+        .map_err(|_| format!("invalid weekday '{}', expected one of mon..sun", input))
+}
+
+fn parse_header(input: &str) -> Result<(String, String), String> {
+    match input.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!(
+            "Invalid header {}, expected \"Name: value\"",
+            input
+        )),
+    }
+}
+
+fn parse_cents(input: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid amount {}, expected e.g. 1234,56", input);
+    let (negative, digits) = match input.trim().strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, input.trim()),
+    };
+    let (units, fraction) = digits.split_once([',', '.']).unwrap_or((digits, "0"));
+    if units.is_empty()
+        || fraction.is_empty()
+        || fraction.len() > 2
+        || !units
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let units: i64 = units.parse().map_err(|_| invalid())?;
+    let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+    let cents = units * 100 + fraction;
+
+    Ok(if negative { -cents } else { cents })
+}
+
 #[cfg(test)]
 mod test {
 
-    use crate::try_into_month;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+
+    use chrono::{Month, NaiveDate, TimeZone, Utc};
+    use clap::CommandFactory;
+    use secrecy::{ExposeSecret, Secret};
+
+    use crate::{
+        account_id, check_skipped, config_init, describe_config, env_template, load_account_ids,
+        output_bytes, output_file_name, parse_cents, parse_header, parse_since, parse_until,
+        post_process, relative_month, resolve_secret, try_into_month, App, Format,
+    };
 
     #[test]
     fn parse_months() -> Result<(), anyhow::Error> {
@@ -119,4 +1424,311 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn parse_month_abbreviations() -> Result<(), anyhow::Error> {
+        let tests = [
+            ("jan", Month::January),
+            ("fev", Month::February),
+            ("Feb", Month::February),
+            ("Março", Month::March),
+            ("sep", Month::September),
+            ("set", Month::September),
+            ("OUT", Month::October),
+            ("dez", Month::December),
+            ("dec", Month::December),
+        ];
+
+        for (input, month) in tests {
+            assert_eq!(try_into_month(input)?, month, "{}", input);
+        }
+        assert!(try_into_month("foo").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_relative_months() {
+        let january = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        assert_eq!(
+            relative_month("this-month", january),
+            Some((Month::January, 2024))
+        );
+        assert_eq!(
+            relative_month("last-month", january),
+            Some((Month::December, 2023))
+        );
+        assert_eq!(
+            relative_month("14-months-ago", january),
+            Some((Month::November, 2022))
+        );
+        assert_eq!(relative_month("march", january), None);
+        assert_eq!(relative_month("x-months-ago", january), None);
+    }
+
+    #[test]
+    fn fail_past_max_skipped() {
+        assert!(check_skipped(3, 3).is_ok());
+        assert!(check_skipped(4, 3).is_err());
+        assert!(check_skipped(100, 0).is_ok());
+    }
+
+    #[test]
+    fn prepend_byte_order_mark() {
+        let ofx = r#"<?xml version="1.0" encoding="UTF-8"?><OFX></OFX>"#;
+
+        let with_bom = output_bytes(ofx, true);
+        assert_eq!(with_bom[..3], [0xEF, 0xBB, 0xBF]);
+        assert!(with_bom[3..].starts_with(b"<?xml"));
+        assert_eq!(output_bytes(ofx, false), ofx.as_bytes());
+    }
+
+    #[test]
+    fn pipe_output_through_command() -> Result<(), anyhow::Error> {
+        assert_eq!(
+            post_process("tr a-z A-Z", "<name>caju</name>".to_string())?,
+            "<NAME>CAJU</NAME>"
+        );
+
+        let error = post_process("cat > /dev/null; exit 3", "ofx".to_string()).unwrap_err();
+        assert!(error.to_string().contains("exit status: 3"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_dates_and_datetimes() {
+        // Days are taken in BRT, and --until includes the whole day
+        assert_eq!(
+            parse_since("2023-06-01"),
+            Ok(Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_until("2023-06-01"),
+            Ok(Utc.with_ymd_and_hms(2023, 6, 2, 3, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_until("2023-06-01T15:30:00-03:00"),
+            Ok(Utc.with_ymd_and_hms(2023, 6, 1, 18, 30, 0).unwrap())
+        );
+        assert!(parse_since("01/06/2023").is_err());
+    }
+
+    #[test]
+    fn parse_dates_during_daylight_saving_time() {
+        // Brazil observed daylight saving time (BRST, -02:00) until 2019
+        assert_eq!(
+            parse_since("2018-12-01"),
+            Ok(Utc.with_ymd_and_hms(2018, 12, 1, 2, 0, 0).unwrap())
+        );
+        // Clocks jumped from midnight to 01:00 on the day it started
+        assert_eq!(
+            parse_since("2018-11-04"),
+            Ok(Utc.with_ymd_and_hms(2018, 11, 4, 3, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_until("2018-11-03"),
+            Ok(Utc.with_ymd_and_hms(2018, 11, 4, 3, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_amounts_in_cents() {
+        assert_eq!(parse_cents("1234,56"), Ok(123456));
+        assert_eq!(parse_cents("1234.5"), Ok(123450));
+        assert_eq!(parse_cents("-10"), Ok(-1000));
+        assert!(parse_cents("12,345").is_err());
+        assert!(parse_cents("1.234,56").is_err());
+        assert!(parse_cents("abc").is_err());
+    }
+
+    #[test]
+    fn reject_unknown_statuses() {
+        let parse = |statuses: &str| {
+            App::command().try_get_matches_from([
+                "caju-importer",
+                "--user-id",
+                "user",
+                "--employee-id",
+                "employee",
+                "--only-status",
+                statuses,
+                "june",
+            ])
+        };
+
+        assert!(parse("confirmed,refunded").is_ok());
+        let error = parse("confirmed,scheduled").expect_err("scheduled is not a status");
+        assert!(error.to_string().contains("pending"), "{}", error);
+    }
+
+    #[test]
+    fn reject_quiet_with_verbose() {
+        let parse = |flags: &[&str]| {
+            App::command().try_get_matches_from(
+                [
+                    "caju-importer",
+                    "--user-id",
+                    "user",
+                    "--employee-id",
+                    "employee",
+                    "june",
+                ]
+                .iter()
+                .chain(flags),
+            )
+        };
+
+        assert!(parse(&["--quiet"]).is_ok());
+        assert!(parse(&["-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn parse_post_headers() {
+        assert_eq!(
+            parse_header("Authorization: Bearer abc:def"),
+            Ok(("Authorization".to_string(), "Bearer abc:def".to_string()))
+        );
+        assert!(parse_header("Authorization").is_err());
+        assert!(parse_header(": value").is_err());
+    }
+
+    #[test]
+    fn name_output_files() {
+        assert_eq!(
+            output_file_name("caju", 2023, chrono::Month::June, Format::Ofx),
+            "caju_202306.ofx"
+        );
+        assert_eq!(
+            output_file_name("caju", 2023, chrono::Month::December, Format::Json),
+            "caju_202312.json"
+        );
+    }
+
+    /// A directory of the test's own, so that tests running in parallel never share files.
+    fn test_dir(test: &str) -> std::io::Result<PathBuf> {
+        let name = format!("caju-importer-{}-{}", std::process::id(), test);
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn map_account_ids() -> Result<(), anyhow::Error> {
+        let dir = test_dir("map_account_ids")?;
+        let path = dir.join("account-ids.json");
+        std::fs::write(
+            &path,
+            r#"{ "caju:alice": "caju-alice", "caju:bob": "caju-bob", "flash": "flash" }"#,
+        )?;
+
+        let account_ids = load_account_ids(&path)?;
+        assert_eq!(
+            account_id(&account_ids, "caju", "alice"),
+            Some("caju-alice")
+        );
+        assert_eq!(account_id(&account_ids, "caju", "bob"), Some("caju-bob"));
+        assert_eq!(account_id(&account_ids, "flash", "alice"), Some("flash"));
+        assert_eq!(account_id(&account_ids, "caju", "carol"), None);
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn scaffold_env_file() -> Result<(), anyhow::Error> {
+        let template = env_template();
+        assert!(template.contains("\nUSER_ID=\n"), "{}", template);
+        assert!(template.contains("\nBEARER_TOKEN=\n"), "{}", template);
+        assert!(
+            template.contains("\n# BEARER_TOKEN_FILE=\n"),
+            "{}",
+            template
+        );
+        assert!(template.contains("\nBASE_URL=https://apigw.caju.com.br\n"));
+
+        let dir = test_dir("scaffold_env_file")?;
+        let path = dir.join(".env");
+        std::fs::write(&path, "USER_ID=existing\n")?;
+        assert!(config_init(&path, false).is_err());
+        assert_eq!(std::fs::read_to_string(&path)?, "USER_ID=existing\n");
+        config_init(&path, true)?;
+        assert_eq!(std::fs::read_to_string(&path)?, template);
+        std::fs::remove_dir_all(&dir)?;
+
+        let matches = App::command().try_get_matches_from(["caju-importer", "config-init"])?;
+        assert_eq!(matches.subcommand_name(), Some("config-init"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_secrets_from_files() -> Result<(), anyhow::Error> {
+        let dir = test_dir("resolve_secrets_from_files")?;
+        let path = dir.join("secret");
+        std::fs::write(&path, "  token-from-file\n")?;
+
+        let secret = resolve_secret("bearer-token", "BEARER_TOKEN", None, Some(&path))?;
+        assert_eq!(secret.expose_secret(), "token-from-file");
+
+        let direct = Some(Secret::new("token".to_string()));
+        assert!(resolve_secret("bearer-token", "BEARER_TOKEN", direct, Some(&path)).is_err());
+        assert!(resolve_secret("bearer-token", "BEARER_TOKEN", None, None).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn describe_config_without_secrets() -> Result<(), anyhow::Error> {
+        let matches = App::command().try_get_matches_from([
+            "caju-importer",
+            "--bearer-token",
+            "very-secret",
+            "--user-id",
+            "user",
+            "--employee-id",
+            "employee",
+            "--post-url",
+            "http://localhost/import",
+            "--post-header",
+            "Authorization: Bearer header-secret",
+            "--post-header",
+            "X-Account: caju",
+            "--show-config",
+            "june",
+        ])?;
+
+        let config = describe_config(&matches, &HashSet::new());
+
+        assert!(config.iter().all(|line| !line.contains("very-secret")));
+        assert!(config.iter().all(|line| !line.contains("header-secret")));
+        let headers = "--post-header = Authorization: ***,X-Account: *** [flag]";
+        assert!(config.contains(&headers.to_string()));
+        assert!(config.contains(&"--bearer-token = *** [flag]".to_string()));
+        assert!(config.contains(&"--user-id = user [flag]".to_string()));
+        assert!(config.contains(&"month = june [flag]".to_string()));
+        assert!(config.contains(&"--max-pages = 1000 [default]".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn show_config_without_required_values() -> Result<(), anyhow::Error> {
+        // Missing values are what --show-config helps track down
+        let matches = App::command().try_get_matches_from(["caju-importer", "--show-config"])?;
+
+        let config = describe_config(&matches, &HashSet::new());
+
+        assert!(config.contains(&"--user-id = <unset> [unset]".to_string()));
+        assert!(config.contains(&"month = <unset> [unset]".to_string()));
+        assert!(App::command()
+            .try_get_matches_from(["caju-importer"])
+            .is_err());
+
+        Ok(())
+    }
 }
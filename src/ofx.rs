@@ -1,59 +1,607 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename = "OFX")]
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::currency;
+use crate::transaction::{Transaction, STATEMENT_TIMEZONE};
+
+#[derive(Debug)]
 pub struct Ofx {
-    #[serde(rename = "BANKMSGSRSV1")]
     pub bank: Option<OfxBanking>,
-    #[serde(rename = "CREDITCARDMSGSRSV1")]
     pub credit_card: Option<OfxCreditCard>,
 }
 
 impl Ofx {
-    pub fn to_ofx(&self) -> Result<String, serde_xml_rs::Error> {
-        serde_xml_rs::to_string(&self)
+    /// Builds a credit card statement holding the given transactions.
+    pub fn credit_card_statement(
+        bank_id: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        transactions: &[Transaction],
+    ) -> Self {
+        debug_assert!(start <= end, "DTSTART must not be after DTEND");
+
+        Ofx {
+            bank: None,
+            credit_card: Some(OfxCreditCard {
+                statement: OfxCreditCardStatement {
+                    transaction_id: "transaction_id".to_string(),
+                    status: OfxStatementStatus {
+                        code: 0,
+                        severity: "INFO".to_string(),
+                    },
+                    statements: OfxStatement {
+                        currency_code: currency::STATEMENT_CURRENCY.to_string(),
+                        bank_account: OfxBankAccount {
+                            bank_id: bank_id.to_string(),
+                            account_id: None,
+                        },
+                        transactions: OfxTransactions {
+                            start: format_timestamp(start),
+                            end: format_timestamp(end),
+                            transactions: transactions
+                                .iter()
+                                .map(|t| OfxTransactionVariant::Transaction(t.into()))
+                                .collect(),
+                        },
+                        account_updated: None,
+                    },
+                },
+            }),
+        }
+    }
+
+    /// Renders the statement as an OFX (XML) document. Elements are always written in the order
+    /// the OFX spec lists them, text is escaped, and missing message sets are left out instead of
+    /// being written as empty elements. Fails when there is neither a bank nor a credit card
+    /// message set, since the document would hold no statement at all.
+    pub fn to_ofx(&self) -> anyhow::Result<String> {
+        self.render(XmlWriter::default())
+    }
+
+    /// Renders the statement like [`Ofx::to_ofx`] but with only the elements strict importers
+    /// require: the message set with its TRNUID and STATUS, CURDEF, BANKACCTFROM, BANKTRANLIST,
+    /// and STMTTRN records with TRNTYPE, DTPOSTED, TRNAMT, FITID and NAME. DTUSER, SIC, PAYEE,
+    /// MEMO and DTACCTUP are left out.
+    pub fn to_minimal_ofx(&self) -> anyhow::Result<String> {
+        self.render(XmlWriter {
+            minimal: true,
+            ..Default::default()
+        })
+    }
+
+    fn render(&self, mut writer: XmlWriter) -> anyhow::Result<String> {
+        if self.bank.is_none() && self.credit_card.is_none() {
+            anyhow::bail!("OFX has neither a bank nor a credit card statement to write");
+        }
+        writer
+            .output
+            .push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        writer.open("OFX");
+        if let Some(bank) = &self.bank {
+            writer.open("BANKMSGSRSV1");
+            writer.open("STMTTRNRS");
+            writer.leaf("TRNUID", &bank.statement.transaction_id);
+            bank.statement.status.write(&mut writer);
+            writer.open("STMTRS");
+            bank.statement.statements.write(&mut writer);
+            writer.close("STMTRS");
+            writer.close("STMTTRNRS");
+            writer.close("BANKMSGSRSV1");
+        }
+        if let Some(credit_card) = &self.credit_card {
+            writer.open("CREDITCARDMSGSRSV1");
+            writer.open("CCSTMTTRNRS");
+            writer.leaf("TRNUID", &credit_card.statement.transaction_id);
+            credit_card.statement.status.write(&mut writer);
+            writer.open("CCSTMTRS");
+            credit_card.statement.statements.write(&mut writer);
+            writer.close("CCSTMTRS");
+            writer.close("CCSTMTTRNRS");
+            writer.close("CREDITCARDMSGSRSV1");
+        }
+        writer.close("OFX");
+        Ok(writer.output)
+    }
+
+    /// Renders only the BANKTRANLIST of the statement, for tools that supply the rest of the OFX
+    /// envelope themselves. The result is not a complete OFX file.
+    pub fn to_transaction_list(&self) -> String {
+        let mut writer = XmlWriter::default();
+        if let Some(statement) = self.statement() {
+            statement.transactions.write(&mut writer);
+        }
+        writer.output
+    }
+
+    /// Parses an OFX file, such as one previously written by [`Ofx::to_ofx`].
+    pub fn from_ofx(input: &str) -> anyhow::Result<Self> {
+        // Written with --bom
+        let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+        let root = Element::parse(input)?;
+        if root.name != "OFX" {
+            return Err(anyhow::anyhow!(
+                "Expected <OFX> root, found <{}>",
+                root.name
+            ));
+        }
+
+        let bank = match root.find_non_empty("BANKMSGSRSV1") {
+            Some(bank) => {
+                let statement = bank.child("STMTTRNRS")?;
+                Some(OfxBanking {
+                    statement: OfxBankingStatement {
+                        transaction_id: statement.text_of("TRNUID")?,
+                        status: OfxStatementStatus::from_element(statement.child("STATUS")?)?,
+                        statements: OfxStatement::from_element(statement.child("STMTRS")?)?,
+                    },
+                })
+            }
+            None => None,
+        };
+
+        let credit_card = match root.find_non_empty("CREDITCARDMSGSRSV1") {
+            Some(credit_card) => {
+                let statement = credit_card.child("CCSTMTTRNRS")?;
+                Some(OfxCreditCard {
+                    statement: OfxCreditCardStatement {
+                        transaction_id: statement.text_of("TRNUID")?,
+                        status: OfxStatementStatus::from_element(statement.child("STATUS")?)?,
+                        statements: OfxStatement::from_element(statement.child("CCSTMTRS")?)?,
+                    },
+                })
+            }
+            None => None,
+        };
+
+        Ok(Ofx { bank, credit_card })
+    }
+
+    /// Merges the transactions of a previously written statement into this one. Transactions
+    /// sharing a FITID are kept only once, preferring the ones already in `self`, and DTSTART/DTEND
+    /// are widened to cover both statements.
+    pub fn merge(&mut self, existing: Ofx) -> anyhow::Result<()> {
+        let existing = existing
+            .into_statement()
+            .ok_or(anyhow::anyhow!("Existing OFX has no statement to merge"))?
+            .transactions;
+        let list = &mut self
+            .statement_mut()
+            .ok_or(anyhow::anyhow!("OFX has no statement to merge into"))?
+            .transactions;
+
+        // Files written while Brazil observed daylight saving time carry a different offset, so
+        // timestamps are compared as instants rather than as strings
+        if parse_timestamp(&existing.start)? < parse_timestamp(&list.start)? {
+            list.start = existing.start;
+        }
+        if parse_timestamp(&existing.end)? > parse_timestamp(&list.end)? {
+            list.end = existing.end;
+        }
+
+        let ids: HashSet<String> = list
+            .transactions
+            .iter()
+            .map(|OfxTransactionVariant::Transaction(t)| t.id.clone())
+            .collect();
+        list.transactions.extend(
+            existing
+                .transactions
+                .into_iter()
+                .filter(|OfxTransactionVariant::Transaction(t)| !ids.contains(&t.id)),
+        );
+        let mut dated = list
+            .transactions
+            .drain(..)
+            .map(|variant| {
+                let OfxTransactionVariant::Transaction(transaction) = &variant;
+                let timestamp = parse_timestamp(&transaction.timestamp)?;
+                Ok((timestamp, transaction.id.clone(), variant))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Same-day transactions are ordered by FITID, so merging is deterministic
+        dated.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        list.transactions = dated.into_iter().map(|(_, _, variant)| variant).collect();
+
+        Ok(())
+    }
+
+    /// Replaces the BANKID, which defaults to the provider name. Some importers only accept
+    /// numeric bank codes there.
+    pub fn with_bank_id(mut self, bank_id: &str) -> Self {
+        if let Some(statement) = self.statement_mut() {
+            statement.bank_account.bank_id = bank_id.to_string();
+        }
+        self
+    }
+
+    /// Sets the ACCTID, which is left out by default, to route the statement to a specific
+    /// account in the importer.
+    pub fn with_account_id(mut self, account_id: &str) -> Self {
+        if let Some(statement) = self.statement_mut() {
+            statement.bank_account.account_id = Some(account_id.to_string());
+        }
+        self
+    }
+
+    /// Prefixes every FITID with `prefix:`, so that ids from different providers imported into
+    /// the same account can't collide. An empty prefix leaves them untouched.
+    pub fn with_fitid_prefix(mut self, prefix: &str) -> Self {
+        if prefix.is_empty() {
+            return self;
+        }
+        if let Some(statement) = self.statement_mut() {
+            for OfxTransactionVariant::Transaction(transaction) in
+                &mut statement.transactions.transactions
+            {
+                transaction.id = format!("{}:{}", prefix, transaction.id);
+            }
+        }
+        self
+    }
+
+    /// Sets the DTACCTUP, which is left out by default, for importers that show when the account
+    /// was last updated.
+    pub fn with_account_updated(mut self, updated: &DateTime<Utc>) -> Self {
+        if let Some(statement) = self.statement_mut() {
+            statement.account_updated = Some(format_datetime(updated));
+        }
+        self
+    }
+
+    fn statement(&self) -> Option<&OfxStatement> {
+        if let Some(credit_card) = self.credit_card.as_ref() {
+            return Some(&credit_card.statement.statements);
+        }
+        self.bank.as_ref().map(|bank| &bank.statement.statements)
+    }
+
+    fn statement_mut(&mut self) -> Option<&mut OfxStatement> {
+        if let Some(credit_card) = self.credit_card.as_mut() {
+            return Some(&mut credit_card.statement.statements);
+        }
+        self.bank
+            .as_mut()
+            .map(|bank| &mut bank.statement.statements)
+    }
+
+    fn into_statement(self) -> Option<OfxStatement> {
+        match (self.credit_card, self.bank) {
+            (Some(credit_card), _) => Some(credit_card.statement.statements),
+            (None, Some(bank)) => Some(bank.statement.statements),
+            (None, None) => None,
+        }
+    }
+}
+
+/// How often an element appears inside its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    Required,
+    Optional,
+    /// Zero or more times.
+    Repeated,
+}
+
+/// An element of the OFX documents written by [`Ofx::to_ofx`], see [`SCHEMA`].
+#[derive(Debug)]
+pub struct SchemaElement {
+    pub name: &'static str,
+    pub presence: Presence,
+    pub children: &'static [SchemaElement],
+}
+
+const fn element(name: &'static str, presence: Presence) -> SchemaElement {
+    SchemaElement {
+        name,
+        presence,
+        children: &[],
+    }
+}
+
+const fn aggregate(
+    name: &'static str,
+    presence: Presence,
+    children: &'static [SchemaElement],
+) -> SchemaElement {
+    SchemaElement {
+        name,
+        presence,
+        children,
+    }
+}
+
+const STATUS: SchemaElement = aggregate(
+    "STATUS",
+    Presence::Required,
+    &[
+        element("CODE", Presence::Required),
+        element("SEVERITY", Presence::Required),
+    ],
+);
+
+const STATEMENT: &[SchemaElement] = &[
+    element("CURDEF", Presence::Required),
+    aggregate(
+        "BANKACCTFROM",
+        Presence::Required,
+        &[
+            element("BANKID", Presence::Required),
+            element("ACCTID", Presence::Optional),
+        ],
+    ),
+    aggregate(
+        "BANKTRANLIST",
+        Presence::Required,
+        &[
+            element("DTSTART", Presence::Required),
+            element("DTEND", Presence::Required),
+            aggregate(
+                "STMTTRN",
+                Presence::Repeated,
+                &[
+                    element("TRNTYPE", Presence::Required),
+                    element("DTPOSTED", Presence::Required),
+                    element("DTUSER", Presence::Optional),
+                    element("TRNAMT", Presence::Required),
+                    element("FITID", Presence::Required),
+                    element("SIC", Presence::Optional),
+                    // Either NAME or PAYEE
+                    element("NAME", Presence::Optional),
+                    aggregate(
+                        "PAYEE",
+                        Presence::Optional,
+                        &[
+                            element("NAME", Presence::Required),
+                            element("ADDR1", Presence::Required),
+                            element("CITY", Presence::Required),
+                            element("STATE", Presence::Required),
+                            element("POSTALCODE", Presence::Required),
+                            element("COUNTRY", Presence::Optional),
+                        ],
+                    ),
+                    element("MEMO", Presence::Optional),
+                ],
+            ),
+        ],
+    ),
+    element("DTACCTUP", Presence::Optional),
+];
+
+/// Element hierarchy of the documents written by [`Ofx::to_ofx`], in the order elements are
+/// written. Kept in sync with the writer by a test.
+pub const SCHEMA: SchemaElement = aggregate(
+    "OFX",
+    Presence::Required,
+    &[
+        aggregate(
+            "BANKMSGSRSV1",
+            Presence::Optional,
+            &[aggregate(
+                "STMTTRNRS",
+                Presence::Required,
+                &[
+                    element("TRNUID", Presence::Required),
+                    STATUS,
+                    aggregate("STMTRS", Presence::Required, STATEMENT),
+                ],
+            )],
+        ),
+        aggregate(
+            "CREDITCARDMSGSRSV1",
+            Presence::Optional,
+            &[aggregate(
+                "CCSTMTTRNRS",
+                Presence::Required,
+                &[
+                    element("TRNUID", Presence::Required),
+                    STATUS,
+                    aggregate("CCSTMTRS", Presence::Required, STATEMENT),
+                ],
+            )],
+        ),
+    ],
+);
+
+impl SchemaElement {
+    /// Renders the element and its children as an indented tree, one element per line.
+    pub fn to_tree(&self) -> String {
+        let mut tree = String::new();
+        self.write_tree(0, &mut tree);
+        tree
+    }
+
+    fn write_tree(&self, depth: usize, tree: &mut String) {
+        let note = match self.presence {
+            Presence::Required => "",
+            Presence::Optional => " (optional)",
+            Presence::Repeated => " (repeated)",
+        };
+        tree.push_str(&format!("{}{}{}\n", "  ".repeat(depth), self.name, note));
+        for child in self.children {
+            child.write_tree(depth + 1, tree);
+        }
+    }
+}
+
+/// Minimal XML tree, enough to walk through the fixed structure of an OFX file.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut stack = vec![Element::default()];
+        for event in EventReader::from_str(input) {
+            match event? {
+                XmlEvent::StartElement { name, .. } => stack.push(Element {
+                    name: name.local_name,
+                    ..Default::default()
+                }),
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text);
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop().expect("xml-rs only ends opened elements");
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.push(element);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        stack
+            .pop()
+            .and_then(|document| document.children.into_iter().next())
+            .ok_or(anyhow::anyhow!("Empty OFX document"))
+    }
+
+    fn find(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn find_non_empty(&self, name: &str) -> Option<&Element> {
+        self.find(name).filter(|c| !c.children.is_empty())
+    }
+
+    fn child(&self, name: &str) -> anyhow::Result<&Element> {
+        self.find(name)
+            .ok_or(anyhow::anyhow!("Missing <{}> in <{}>", name, self.name))
+    }
+
+    fn text_of(&self, name: &str) -> anyhow::Result<String> {
+        Ok(self.child(name)?.text.trim().to_string())
+    }
+}
+
+/// Writes the small, fixed set of elements OFX needs. Every element holds either text or other
+/// elements, so there's no need for attributes or self-closing tags.
+#[derive(Debug, Default)]
+struct XmlWriter {
+    output: String,
+    /// Skip the optional elements, see [`Ofx::to_minimal_ofx`].
+    minimal: bool,
+}
+
+impl XmlWriter {
+    fn open(&mut self, name: &str) {
+        self.output.push('<');
+        self.output.push_str(name);
+        self.output.push('>');
+    }
+
+    fn close(&mut self, name: &str) {
+        self.output.push_str("</");
+        self.output.push_str(name);
+        self.output.push('>');
+    }
+
+    fn leaf(&mut self, name: &str, text: &str) {
+        self.open(name);
+        for c in text.chars() {
+            match c {
+                '&' => self.output.push_str("&amp;"),
+                '<' => self.output.push_str("&lt;"),
+                '>' => self.output.push_str("&gt;"),
+                '"' => self.output.push_str("&quot;"),
+                '\'' => self.output.push_str("&apos;"),
+                c => self.output.push(c),
+            }
+        }
+        self.close(name);
+    }
+}
+
+/// Formats the date in São Paulo time, with the offset in effect at that instant, since Brazil
+/// observed daylight saving time (BRST, -2) until 2019.
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    let local = timestamp.with_timezone(&STATEMENT_TIMEZONE);
+    let offset = local.offset().fix().local_minus_utc() / 3600;
+    local
+        .format(&format!("%Y%m%d000000[{}:{}]", offset, offset_name(offset)))
+        .to_string()
+}
+
+/// Parses an OFX date such as "20230630153000[-3:BRT]", as written by [`format_timestamp`] and
+/// [`format_datetime`]. The time, its milliseconds and the bracketed offset are optional, and
+/// dates without an offset are taken in UTC, as the OFX spec says.
+fn parse_timestamp(text: &str) -> anyhow::Result<DateTime<Utc>> {
+    let invalid = || anyhow::anyhow!("Invalid OFX date '{}'", text);
+    let (local, offset) = match text.trim().split_once('[') {
+        Some((local, zone)) => {
+            let zone = zone.strip_suffix(']').ok_or_else(invalid)?;
+            let (hours, _name) = zone.split_once(':').unwrap_or((zone, ""));
+            let hours: f64 = hours.parse().map_err(|_| invalid())?;
+            (local, (hours * 3600.0).round() as i32)
+        }
+        None => (text.trim(), 0),
+    };
+
+    let (local, _milliseconds) = local.split_once('.').unwrap_or((local, ""));
+    let naive = match local.len() {
+        8 => NaiveDate::parse_from_str(local, "%Y%m%d").map(|date| date.and_time(NaiveTime::MIN)),
+        12 => NaiveDateTime::parse_from_str(local, "%Y%m%d%H%M"),
+        14 => NaiveDateTime::parse_from_str(local, "%Y%m%d%H%M%S"),
+        _ => return Err(invalid()),
     }
+    .map_err(|_| invalid())?;
+    FixedOffset::east_opt(offset)
+        .and_then(|offset| offset.from_local_datetime(&naive).single())
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok_or_else(invalid)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename = "BANKMSGSRSV1")]
+/// Formats the instant in São Paulo time down to the second, e.g. "20230630153000[-3:BRT]".
+fn format_datetime(timestamp: &DateTime<Utc>) -> String {
+    let local = timestamp.with_timezone(&STATEMENT_TIMEZONE);
+    let offset = local.offset().fix().local_minus_utc() / 3600;
+    local
+        .format(&format!("%Y%m%d%H%M%S[{}:{}]", offset, offset_name(offset)))
+        .to_string()
+}
+
+fn offset_name(offset: i32) -> &'static str {
+    match offset {
+        -3 => "BRT",
+        -2 => "BRST",
+        _ => "LMT",
+    }
+}
+
+#[derive(Debug)]
 pub struct OfxBanking {
-    #[serde(rename = "STMTTRNRS")]
     pub statement: OfxBankingStatement,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct OfxBankingStatement {
-    #[serde(rename = "TRNUID")]
     pub transaction_id: String,
-    #[serde(rename = "STATUS")]
     pub status: OfxStatementStatus,
-    #[serde(rename = "STMTRS")]
     pub statements: OfxStatement,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename = "CREDITCARDMSGSRSV1")]
+#[derive(Debug)]
 pub struct OfxCreditCard {
-    #[serde(rename = "CCSTMTTRNRS")]
     pub statement: OfxCreditCardStatement,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct OfxCreditCardStatement {
-    #[serde(rename = "TRNUID")]
     pub transaction_id: String,
-    #[serde(rename = "STATUS")]
     pub status: OfxStatementStatus,
-    #[serde(rename = "CCSTMTRS")]
     pub statements: OfxStatement,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct OfxStatementStatus {
-    #[serde(rename = "CODE")]
     pub code: usize,
-    #[serde(rename = "SEVERITY")]
     pub severity: String,
 }
 
@@ -62,19 +610,13 @@ pub struct OfxStatementStatus {
 ///   <CURDEF>BRL</CURDEF>
 ///   <BANKACCTFROM> ... </BANKACCTFROM>
 ///   <BANKTRANLIST> ... </BANKTRANLIST>
-///   <LEDGERBAL> ... </LEDGERBAL>
-///   <BALLIST> ... </BALLIST>
 /// </STMTRS>
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct OfxStatement {
-    #[serde(rename = "CURDEF")]
     pub currency_code: String,
-    #[serde(rename = "BANKACCTFROM")]
     pub bank_account: OfxBankAccount,
-    #[serde(rename = "BANKTRANLIST")]
     pub transactions: OfxTransactions,
-    // #[serde(rename = "LEDGERBAL")]
-    // pub ledger_balance: OfxLedgerBalance,
+    pub account_updated: Option<String>,
 }
 
 /// <BANKACCTFROM>
@@ -83,43 +625,708 @@ pub struct OfxStatement {
 ///   <ACCTID>0000000-0</ACCTID>
 ///   <ACCTTYPE>CHECKING</ACCTTYPE>
 /// </BANKACCTFROM>
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct OfxBankAccount {
-    #[serde(rename = "BANKID")]
     pub bank_id: String,
+    pub account_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename = "BANKTRANLIST")]
+#[derive(Debug)]
 pub struct OfxTransactions {
-    #[serde(rename = "DTSTART")]
     pub start: String,
-    #[serde(rename = "DTEND")]
     pub end: String,
-    #[serde(rename = "$value")]
     pub transactions: Vec<OfxTransactionVariant>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub enum OfxTransactionVariant {
-    #[serde(rename = "STMTTRN")]
     Transaction(OfxTransaction),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename = "STMTTRN")]
+#[derive(Debug)]
 pub struct OfxTransaction {
-    #[serde(rename = "TRNTYPE")]
     pub type_: String,
-    #[serde(rename = "DTPOSTED")]
     pub timestamp: String,
-    #[serde(rename = "TRNAMT")]
+    pub user_date: Option<String>,
     pub amount: String,
-    #[serde(rename = "FITID")]
     pub id: String,
-    #[serde(rename = "MEMO")]
+    pub category_code: Option<String>,
+    pub name: String,
+    /// Written instead of NAME when set.
+    pub payee: Option<OfxPayee>,
     pub description: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OfxLedgerBalance {}
+/// <PAYEE>
+///   <NAME>Padaria</NAME>
+///   <ADDR1>Rua Augusta, 100</ADDR1>
+///   <CITY>São Paulo</CITY>
+///   <STATE>SP</STATE>
+///   <POSTALCODE>01305-000</POSTALCODE>
+///   <COUNTRY>BRA</COUNTRY>
+/// </PAYEE>
+#[derive(Debug)]
+pub struct OfxPayee {
+    pub name: String,
+    pub address: String,
+    pub city: String,
+    pub state: String,
+    pub postal_code: String,
+    pub country: Option<String>,
+}
+
+impl OfxPayee {
+    fn write(&self, writer: &mut XmlWriter) {
+        writer.open("PAYEE");
+        writer.leaf("NAME", &self.name);
+        writer.leaf("ADDR1", &self.address);
+        writer.leaf("CITY", &self.city);
+        writer.leaf("STATE", &self.state);
+        writer.leaf("POSTALCODE", &self.postal_code);
+        if let Some(country) = &self.country {
+            writer.leaf("COUNTRY", country);
+        }
+        writer.close("PAYEE");
+    }
+
+    fn from_element(element: &Element) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: element.text_of("NAME")?,
+            address: element.text_of("ADDR1")?,
+            city: element.text_of("CITY")?,
+            state: element.text_of("STATE")?,
+            postal_code: element.text_of("POSTALCODE")?,
+            country: element.text_of("COUNTRY").ok(),
+        })
+    }
+}
+
+impl From<&Transaction> for OfxTransaction {
+    fn from(value: &Transaction) -> Self {
+        Self {
+            type_: value.type_.clone(),
+            timestamp: format_timestamp(&value.date),
+            user_date: value.initiated_at.as_ref().map(format_timestamp),
+            amount: currency::format_amount(value.amount_cents, currency::STATEMENT_CURRENCY),
+            id: value.id.clone(),
+            category_code: value.category_code.clone(),
+            name: value.payee.clone(),
+            // Providers don't send merchant addresses yet
+            payee: None,
+            description: value.memo.clone(),
+        }
+    }
+}
+
+impl OfxStatementStatus {
+    fn write(&self, writer: &mut XmlWriter) {
+        writer.open("STATUS");
+        writer.leaf("CODE", &self.code.to_string());
+        writer.leaf("SEVERITY", &self.severity);
+        writer.close("STATUS");
+    }
+
+    fn from_element(element: &Element) -> anyhow::Result<Self> {
+        Ok(Self {
+            code: element.text_of("CODE")?.parse()?,
+            severity: element.text_of("SEVERITY")?,
+        })
+    }
+}
+
+impl OfxStatement {
+    fn write(&self, writer: &mut XmlWriter) {
+        writer.leaf("CURDEF", &self.currency_code);
+        writer.open("BANKACCTFROM");
+        writer.leaf("BANKID", &self.bank_account.bank_id);
+        if let Some(account_id) = &self.bank_account.account_id {
+            writer.leaf("ACCTID", account_id);
+        }
+        writer.close("BANKACCTFROM");
+        self.transactions.write(writer);
+        if let (Some(updated), false) = (&self.account_updated, writer.minimal) {
+            writer.leaf("DTACCTUP", updated);
+        }
+    }
+
+    fn from_element(element: &Element) -> anyhow::Result<Self> {
+        let list = element.child("BANKTRANLIST")?;
+        Ok(Self {
+            currency_code: element.text_of("CURDEF")?,
+            bank_account: OfxBankAccount {
+                bank_id: element.child("BANKACCTFROM")?.text_of("BANKID")?,
+                account_id: element.child("BANKACCTFROM")?.text_of("ACCTID").ok(),
+            },
+            transactions: OfxTransactions {
+                start: list.text_of("DTSTART")?,
+                end: list.text_of("DTEND")?,
+                transactions: list
+                    .children
+                    .iter()
+                    .filter(|c| c.name == "STMTTRN")
+                    .map(|c| {
+                        OfxTransaction::from_element(c).map(OfxTransactionVariant::Transaction)
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            },
+            account_updated: element.text_of("DTACCTUP").ok(),
+        })
+    }
+}
+
+impl OfxTransactions {
+    fn write(&self, writer: &mut XmlWriter) {
+        writer.open("BANKTRANLIST");
+        writer.leaf("DTSTART", &self.start);
+        writer.leaf("DTEND", &self.end);
+        for OfxTransactionVariant::Transaction(transaction) in &self.transactions {
+            transaction.write(writer);
+        }
+        writer.close("BANKTRANLIST");
+    }
+}
+
+impl OfxTransaction {
+    fn write(&self, writer: &mut XmlWriter) {
+        writer.open("STMTTRN");
+        writer.leaf("TRNTYPE", &self.type_);
+        writer.leaf("DTPOSTED", &self.timestamp);
+        if let (Some(user_date), false) = (&self.user_date, writer.minimal) {
+            writer.leaf("DTUSER", user_date);
+        }
+        writer.leaf("TRNAMT", &self.amount);
+        writer.leaf("FITID", &self.id);
+        if let (Some(category_code), false) = (&self.category_code, writer.minimal) {
+            writer.leaf("SIC", category_code);
+        }
+        match (&self.payee, writer.minimal) {
+            (Some(payee), false) => payee.write(writer),
+            _ => writer.leaf("NAME", &self.name),
+        }
+        if !writer.minimal {
+            writer.leaf("MEMO", &self.description);
+        }
+        writer.close("STMTTRN");
+    }
+
+    fn from_element(element: &Element) -> anyhow::Result<Self> {
+        let description = element.text_of("MEMO")?;
+        let payee = match element.child("PAYEE") {
+            Ok(payee) => Some(OfxPayee::from_element(payee)?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            type_: element.text_of("TRNTYPE")?,
+            timestamp: element.text_of("DTPOSTED")?,
+            user_date: element.text_of("DTUSER").ok(),
+            amount: element.text_of("TRNAMT")?,
+            id: element.text_of("FITID")?,
+            category_code: element.text_of("SIC").ok(),
+            // Files written before NAME was emitted only carry MEMO
+            name: match &payee {
+                Some(payee) => payee.name.clone(),
+                None => element
+                    .text_of("NAME")
+                    .unwrap_or_else(|_| description.clone()),
+            },
+            payee,
+            description,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::BTreeSet;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::ofx::{
+        format_datetime, format_timestamp, parse_timestamp, Element, Ofx, OfxBanking,
+        OfxBankingStatement, OfxPayee, OfxTransactionVariant, SchemaElement, SCHEMA,
+    };
+    use crate::transaction::{Transaction, TransactionStatus};
+
+    fn transaction(id: &str, day: u32) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            date: Utc.with_ymd_and_hms(2023, 6, day, 12, 0, 0).unwrap(),
+            initiated_at: None,
+            amount_cents: -100 * day as i64,
+            type_: "DEBIT".to_string(),
+            category_code: None,
+            payee: format!("Merchant {}", id),
+            memo: format!("Merchant {}", id),
+            status: TransactionStatus::Confirmed,
+        }
+    }
+
+    fn statement(first_day: u32, last_day: u32, transactions: &[Transaction]) -> Ofx {
+        // Midnight in BRT
+        Ofx::credit_card_statement(
+            "Caju",
+            &Utc.with_ymd_and_hms(2023, 6, first_day, 3, 0, 0).unwrap(),
+            &Utc.with_ymd_and_hms(2023, 6, last_day, 3, 0, 0).unwrap(),
+            transactions,
+        )
+    }
+
+    #[test]
+    fn write_statement_snapshot() -> Result<(), anyhow::Error> {
+        let mut purchase = transaction("a", 2);
+        purchase.payee = "Bar & Grill <Centro>".to_string();
+        purchase.memo = "\"Happy\" hour".to_string();
+
+        let output = statement(1, 30, &[purchase]).to_ofx()?;
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "<OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS>",
+                "<TRNUID>transaction_id</TRNUID>",
+                "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>",
+                "<CCSTMTRS><CURDEF>BRL</CURDEF>",
+                "<BANKACCTFROM><BANKID>Caju</BANKID></BANKACCTFROM>",
+                "<BANKTRANLIST>",
+                "<DTSTART>20230601000000[-3:BRT]</DTSTART><DTEND>20230630000000[-3:BRT]</DTEND>",
+                "<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20230602000000[-3:BRT]</DTPOSTED>",
+                "<TRNAMT>-2.00</TRNAMT><FITID>a</FITID>",
+                "<NAME>Bar &amp; Grill &lt;Centro&gt;</NAME><MEMO>&quot;Happy&quot; hour</MEMO>",
+                "</STMTTRN></BANKTRANLIST></CCSTMTRS>",
+                "</CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>",
+            )
+        );
+
+        // Escaped text reads back as it was
+        let parsed = Ofx::from_ofx(&output)?;
+        let credit_card = parsed
+            .credit_card
+            .expect("parsed statement should be a credit card statement");
+        let OfxTransactionVariant::Transaction(transaction) =
+            &credit_card.statement.statements.transactions.transactions[0];
+        assert_eq!(transaction.name, "Bar & Grill <Centro>");
+        assert_eq!(transaction.description, "\"Happy\" hour");
+        assert!(parsed.bank.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_transaction_list_only() {
+        let output =
+            statement(1, 30, &[transaction("a", 2), transaction("b", 3)]).to_transaction_list();
+
+        assert!(output.starts_with("<BANKTRANLIST><DTSTART>20230601000000[-3:BRT]</DTSTART>"));
+        assert!(output.ends_with("</STMTTRN></BANKTRANLIST>"));
+        assert_eq!(output.matches("<STMTTRN>").count(), 2);
+        assert!(!output.contains("<?xml"));
+    }
+
+    #[test]
+    fn write_user_date_when_known() -> Result<(), anyhow::Error> {
+        let mut initiated = transaction("a", 3);
+        initiated.initiated_at = Some(Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap());
+
+        let output = statement(1, 30, &[initiated, transaction("b", 4)]).to_ofx()?;
+        assert!(output.contains(
+            "<DTPOSTED>20230603000000[-3:BRT]</DTPOSTED><DTUSER>20230601000000[-3:BRT]</DTUSER>"
+        ));
+        assert!(output.contains("<DTPOSTED>20230604000000[-3:BRT]</DTPOSTED><TRNAMT>"));
+        assert_eq!(output.matches("<DTUSER>").count(), 1);
+
+        let parsed = Ofx::from_ofx(&output)?;
+        let user_dates: Vec<_> = parsed
+            .credit_card
+            .expect("parsed statement should be a credit card statement")
+            .statement
+            .statements
+            .transactions
+            .transactions
+            .into_iter()
+            .map(|OfxTransactionVariant::Transaction(t)| t.user_date)
+            .collect();
+        assert_eq!(
+            user_dates,
+            vec![Some("20230601000000[-3:BRT]".to_string()), None]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_sic_when_known() -> Result<(), anyhow::Error> {
+        let mut restaurant = transaction("a", 2);
+        restaurant.category_code = Some("5812".to_string());
+
+        let output = statement(1, 30, &[restaurant, transaction("b", 3)]).to_ofx()?;
+
+        assert!(output.contains("<FITID>a</FITID><SIC>5812</SIC><NAME>"));
+        assert!(output.contains("<FITID>b</FITID><NAME>"));
+
+        Ok(())
+    }
+
+    fn schema_paths(element: &SchemaElement, parent: &str, paths: &mut BTreeSet<String>) {
+        let path = format!("{}/{}", parent, element.name);
+        for child in element.children {
+            schema_paths(child, &path, paths);
+        }
+        paths.insert(path);
+    }
+
+    fn written_paths(element: &Element, parent: &str, paths: &mut BTreeSet<String>) {
+        let path = format!("{}/{}", parent, element.name);
+        for child in &element.children {
+            written_paths(child, &path, paths);
+        }
+        paths.insert(path);
+    }
+
+    #[test]
+    fn schema_matches_written_elements() -> Result<(), anyhow::Error> {
+        let mut located = transaction("a", 2);
+        located.initiated_at = Some(Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap());
+        located.category_code = Some("5812".to_string());
+        let mut ofx = statement(1, 30, &[located, transaction("b", 3)])
+            .with_account_id("1234")
+            .with_account_updated(&Utc.with_ymd_and_hms(2023, 7, 1, 9, 0, 0).unwrap());
+        let credit_card = ofx
+            .credit_card
+            .as_mut()
+            .expect("statement should be a credit card statement");
+        let OfxTransactionVariant::Transaction(first) =
+            &mut credit_card.statement.statements.transactions.transactions[0];
+        first.payee = Some(OfxPayee {
+            name: "Padaria".to_string(),
+            address: "Rua Augusta, 100".to_string(),
+            city: "São Paulo".to_string(),
+            state: "SP".to_string(),
+            postal_code: "01305-000".to_string(),
+            country: Some("BRA".to_string()),
+        });
+        // The same statement as a bank statement, to cover both message sets
+        let bank = Ofx::from_ofx(&ofx.to_ofx()?)?
+            .credit_card
+            .expect("parsed statement should be a credit card statement")
+            .statement;
+        ofx.bank = Some(OfxBanking {
+            statement: OfxBankingStatement {
+                transaction_id: bank.transaction_id,
+                status: bank.status,
+                statements: bank.statements,
+            },
+        });
+
+        let mut written = BTreeSet::new();
+        written_paths(&Element::parse(&ofx.to_ofx()?)?, "", &mut written);
+        let mut documented = BTreeSet::new();
+        schema_paths(&SCHEMA, "", &mut documented);
+        assert_eq!(written, documented);
+
+        let tree = SCHEMA.to_tree();
+        assert!(tree.starts_with("OFX\n  BANKMSGSRSV1 (optional)\n    STMTTRNRS\n"));
+        assert!(tree.contains("\n        BANKTRANLIST\n          DTSTART\n"));
+        assert!(tree.contains("\n          STMTTRN (repeated)\n            TRNTYPE\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_payee_aggregate_when_known() -> Result<(), anyhow::Error> {
+        let mut ofx = statement(1, 30, &[transaction("a", 2), transaction("b", 3)]);
+        let list = &mut ofx
+            .credit_card
+            .as_mut()
+            .expect("statement should be a credit card statement")
+            .statement
+            .statements
+            .transactions;
+        let OfxTransactionVariant::Transaction(located) = &mut list.transactions[0];
+        located.payee = Some(OfxPayee {
+            name: "Padaria".to_string(),
+            address: "Rua Augusta, 100".to_string(),
+            city: "São Paulo".to_string(),
+            state: "SP".to_string(),
+            postal_code: "01305-000".to_string(),
+            country: None,
+        });
+
+        let output = ofx.to_ofx()?;
+        assert!(output.contains(concat!(
+            "<FITID>a</FITID><PAYEE><NAME>Padaria</NAME><ADDR1>Rua Augusta, 100</ADDR1>",
+            "<CITY>São Paulo</CITY><STATE>SP</STATE><POSTALCODE>01305-000</POSTALCODE>",
+            "</PAYEE><MEMO>",
+        )));
+        assert!(output.contains("<FITID>b</FITID><NAME>Merchant b</NAME><MEMO>"));
+        assert!(ofx
+            .to_minimal_ofx()?
+            .contains("<FITID>a</FITID><NAME>Merchant a</NAME>"));
+
+        let parsed = Ofx::from_ofx(&output)?
+            .credit_card
+            .expect("parsed statement should be a credit card statement");
+        let OfxTransactionVariant::Transaction(located) =
+            &parsed.statement.statements.transactions.transactions[0];
+        assert_eq!(located.name, "Padaria");
+        assert_eq!(
+            located.payee.as_ref().map(|payee| payee.city.as_str()),
+            Some("São Paulo")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn override_bank_id() -> Result<(), anyhow::Error> {
+        let output = statement(1, 30, &[transaction("a", 2)]).to_ofx()?;
+        assert!(output.contains("<BANKID>Caju</BANKID>"));
+
+        let output = statement(1, 30, &[transaction("a", 2)])
+            .with_bank_id("0001")
+            .to_ofx()?;
+        assert!(output.contains("<BANKID>0001</BANKID>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_account_update_only_when_set() -> Result<(), anyhow::Error> {
+        let plain = statement(1, 30, &[transaction("a", 2)]);
+        assert!(!plain.to_ofx()?.contains("DTACCTUP"));
+
+        let updated = statement(1, 30, &[transaction("a", 2)])
+            .with_account_updated(&Utc.with_ymd_and_hms(2023, 7, 1, 18, 30, 15).unwrap());
+        let output = updated.to_ofx()?;
+        assert!(output
+            .contains("</BANKTRANLIST><DTACCTUP>20230701153015[-3:BRT]</DTACCTUP></CCSTMTRS>"));
+        assert!(!updated.to_minimal_ofx()?.contains("DTACCTUP"));
+
+        let parsed = Ofx::from_ofx(&output)?
+            .credit_card
+            .expect("parsed statement should be a credit card statement");
+        assert_eq!(
+            parsed.statement.statements.account_updated.as_deref(),
+            Some("20230701153015[-3:BRT]")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn refuse_to_write_without_statement() {
+        let empty = Ofx {
+            bank: None,
+            credit_card: None,
+        };
+
+        let error = empty
+            .to_ofx()
+            .expect_err("an empty OFX should not be written");
+        assert!(format!("{:#}", error).contains("neither a bank nor a credit card"));
+        assert!(empty.to_minimal_ofx().is_err());
+    }
+
+    #[test]
+    fn write_zero_debits_without_sign() -> Result<(), anyhow::Error> {
+        // Debits are negated when converted, which for zero amounts is still 0
+        let mut zero = transaction("a", 2);
+        zero.amount_cents = 0;
+
+        let output = statement(1, 30, &[zero]).to_ofx()?;
+        assert!(output.contains("<TRNTYPE>DEBIT</TRNTYPE>"));
+        assert!(output.contains("<TRNAMT>0.00</TRNAMT>"), "{}", output);
+        assert!(!output.contains("-0.00"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_account_id() -> Result<(), anyhow::Error> {
+        let output = statement(1, 30, &[transaction("a", 2)]).to_ofx()?;
+        assert!(output.contains("<BANKACCTFROM><BANKID>Caju</BANKID></BANKACCTFROM>"));
+
+        let output = statement(1, 30, &[transaction("a", 2)])
+            .with_account_id("1234-5")
+            .to_ofx()?;
+        assert!(output.contains("<BANKID>Caju</BANKID><ACCTID>1234-5</ACCTID></BANKACCTFROM>"));
+
+        let account = Ofx::from_ofx(&output)?
+            .credit_card
+            .expect("parsed statement should be a credit card statement")
+            .statement
+            .statements
+            .bank_account;
+        assert_eq!(account.account_id.as_deref(), Some("1234-5"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_fitids_with_provider() -> Result<(), anyhow::Error> {
+        let output = statement(1, 30, &[transaction("a", 2)])
+            .with_fitid_prefix("caju")
+            .to_ofx()?;
+        assert!(output.contains("<FITID>caju:a</FITID>"));
+
+        let output = statement(1, 30, &[transaction("a", 2)])
+            .with_fitid_prefix("")
+            .to_ofx()?;
+        assert!(output.contains("<FITID>a</FITID>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ofx_dates() -> Result<(), anyhow::Error> {
+        let at = |year, month, day, hour, minute| {
+            Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+                .unwrap()
+        };
+        let tests = [
+            ("20230630153000[-3:BRT]", at(2023, 6, 30, 18, 30)),
+            ("20181215120000.000[-2:BRST]", at(2018, 12, 15, 14, 0)),
+            ("20230630000000[-3]", at(2023, 6, 30, 3, 0)),
+            ("20230630120000[+5.5:IST]", at(2023, 6, 30, 6, 30)),
+            ("202306301530", at(2023, 6, 30, 15, 30)),
+            ("20230630", at(2023, 6, 30, 0, 0)),
+        ];
+        for (text, expected) in tests {
+            assert_eq!(parse_timestamp(text)?, expected, "{}", text);
+        }
+
+        let instant = Utc.with_ymd_and_hms(2023, 7, 1, 18, 30, 15).unwrap();
+        assert_eq!(parse_timestamp(&format_datetime(&instant))?, instant);
+
+        let invalid = [
+            "",
+            "2023063",
+            "20230631",
+            "20230630120000[BRT]",
+            "20230630120000[-3:BRT",
+        ];
+        for text in invalid {
+            assert!(parse_timestamp(text).is_err(), "{}", text);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_overlapping_statements() -> Result<(), anyhow::Error> {
+        let existing = statement(1, 15, &[transaction("a", 2), transaction("b", 10)]);
+        let mut fresh = statement(
+            10,
+            30,
+            &[
+                transaction("b", 10),
+                transaction("c", 20),
+                transaction("d", 25),
+            ],
+        );
+
+        // Goes through a file, as `--merge` does
+        fresh.merge(Ofx::from_ofx(&existing.to_ofx()?)?)?;
+
+        let list = fresh
+            .credit_card
+            .expect("merged statement should be a credit card statement")
+            .statement
+            .statements
+            .transactions;
+        let ids: Vec<_> = list
+            .transactions
+            .iter()
+            .map(|OfxTransactionVariant::Transaction(t)| t.id.as_str())
+            .collect();
+
+        assert_eq!(ids, vec!["a", "b", "c", "d"]);
+        assert_eq!(list.start, "20230601000000[-3:BRT]");
+        assert_eq!(list.end, "20230630000000[-3:BRT]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_statements_across_offsets() -> Result<(), anyhow::Error> {
+        // Dates from a file written during daylight saving time, which sort before the ones in
+        // BRT as instants but after them as strings
+        let mut existing = statement(1, 15, &[transaction("x", 2), transaction("b", 10)]);
+        let list = &mut existing
+            .statement_mut()
+            .expect("statement should be a credit card statement")
+            .transactions;
+        list.start = "20230601003000[-2:BRST]".to_string();
+        let OfxTransactionVariant::Transaction(x) = &mut list.transactions[0];
+        x.timestamp = "20230602003000[-2:BRST]".to_string();
+        let mut fresh = statement(1, 30, &[transaction("c", 10), transaction("a", 2)]);
+
+        fresh.merge(existing)?;
+
+        let list = fresh
+            .credit_card
+            .expect("merged statement should be a credit card statement")
+            .statement
+            .statements
+            .transactions;
+        let ids: Vec<_> = list
+            .transactions
+            .iter()
+            .map(|OfxTransactionVariant::Transaction(t)| t.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["x", "a", "b", "c"]);
+        assert_eq!(list.start, "20230601003000[-2:BRST]");
+        assert_eq!(list.end, "20230630000000[-3:BRT]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_minimal_snapshot() -> Result<(), anyhow::Error> {
+        let mut purchase = transaction("a", 2);
+        purchase.initiated_at = Some(Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap());
+        purchase.category_code = Some("5812".to_string());
+
+        let output = statement(1, 30, &[purchase])
+            .with_account_updated(&Utc.with_ymd_and_hms(2023, 7, 1, 9, 0, 0).unwrap())
+            .to_minimal_ofx()?;
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "<OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS>",
+                "<TRNUID>transaction_id</TRNUID>",
+                "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>",
+                "<CCSTMTRS><CURDEF>BRL</CURDEF>",
+                "<BANKACCTFROM><BANKID>Caju</BANKID></BANKACCTFROM>",
+                "<BANKTRANLIST>",
+                "<DTSTART>20230601000000[-3:BRT]</DTSTART><DTEND>20230630000000[-3:BRT]</DTEND>",
+                "<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20230602000000[-3:BRT]</DTPOSTED>",
+                "<TRNAMT>-2.00</TRNAMT><FITID>a</FITID><NAME>Merchant a</NAME>",
+                "</STMTTRN></BANKTRANLIST></CCSTMTRS>",
+                "</CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>",
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn format_offsets_with_daylight_saving() {
+        let summer_2018 = Utc.with_ymd_and_hms(2018, 11, 15, 12, 0, 0).unwrap();
+        let summer_2020 = Utc.with_ymd_and_hms(2020, 11, 15, 12, 0, 0).unwrap();
+
+        assert_eq!(format_timestamp(&summer_2018), "20181115000000[-2:BRST]");
+        assert_eq!(format_timestamp(&summer_2020), "20201115000000[-3:BRT]");
+    }
+
+    #[test]
+    fn format_dates_in_statement_timezone() {
+        // 01:00 UTC on June 2nd is still June 1st in BRT
+        let late_night = Utc.with_ymd_and_hms(2023, 6, 2, 1, 0, 0).unwrap();
+
+        assert_eq!(format_timestamp(&late_night), "20230601000000[-3:BRT]");
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::Duration;
+
+use crate::transaction::{Transaction, TransactionStatus};
+
+/// Links refunds to the purchases they revert. A refund matches a confirmed purchase with the
+/// same amount and payee made at most `window` before it. Matched refunds become credits
+/// and both MEMOs are annotated with a shared reference, while unmatched refunds are left alone.
+/// Returns how many pairs were linked.
+pub fn link_refunds(transactions: &mut [Transaction], window: Duration) -> usize {
+    let mut used = vec![false; transactions.len()];
+    let mut linked = 0;
+
+    for refund in 0..transactions.len() {
+        if transactions[refund].status != TransactionStatus::Refunded {
+            continue;
+        }
+
+        let purchase = (0..transactions.len()).find(|&i| {
+            let (purchase, refund) = (&transactions[i], &transactions[refund]);
+            !used[i]
+                && purchase.status == TransactionStatus::Confirmed
+                && purchase.amount_cents.abs() == refund.amount_cents.abs()
+                && purchase.payee == refund.payee
+                && purchase.date <= refund.date
+                && refund.date - purchase.date <= window
+        });
+        let Some(purchase) = purchase else {
+            continue;
+        };
+        used[purchase] = true;
+        linked += 1;
+
+        let reference = format!("[refund:{}]", transactions[purchase].id);
+        transactions[purchase].memo = format!("{} {}", transactions[purchase].memo, reference);
+
+        let refund = &mut transactions[refund];
+        refund.memo = format!("{} {}", refund.memo, reference);
+        refund.amount_cents = refund.amount_cents.abs();
+        refund.type_ = "CREDIT".to_string();
+        refund.status = TransactionStatus::Confirmed;
+    }
+
+    linked
+}
+
+#[cfg(test)]
+mod test {
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::caju::{statement_to_transactions, ConversionOptions};
+    use crate::refund::link_refunds;
+    use crate::transaction::{Transaction, TransactionStatus};
+
+    fn transaction(id: &str, day: u32, status: TransactionStatus) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            date: Utc.with_ymd_and_hms(2023, 6, day, 12, 0, 0).unwrap(),
+            initiated_at: None,
+            amount_cents: -2590,
+            type_: "DEBIT".to_string(),
+            category_code: None,
+            payee: "iFood".to_string(),
+            memo: "iFood".to_string(),
+            status,
+        }
+    }
+
+    #[test]
+    fn link_refund_to_purchase() {
+        let mut transactions = vec![
+            transaction("purchase", 1, TransactionStatus::Confirmed),
+            transaction("other", 2, TransactionStatus::Confirmed),
+            transaction("refund", 3, TransactionStatus::Refunded),
+        ];
+        transactions[1].payee = "Padaria".to_string();
+        transactions[1].memo = "Padaria".to_string();
+
+        assert_eq!(link_refunds(&mut transactions, Duration::days(30)), 1);
+
+        assert_eq!(transactions[0].memo, "iFood [refund:purchase]");
+        assert_eq!(transactions[1].memo, "Padaria");
+        assert_eq!(transactions[2].memo, "iFood [refund:purchase]");
+        assert_eq!(transactions[2].amount_cents, 2590);
+        assert_eq!(transactions[2].type_, "CREDIT");
+        assert_eq!(transactions[2].status, TransactionStatus::Confirmed);
+    }
+
+    #[test]
+    fn leave_unmatched_refunds_alone() {
+        let mut transactions = vec![
+            transaction("refund", 3, TransactionStatus::Refunded),
+            // Made after the refund, so it can't be what was refunded
+            transaction("purchase", 10, TransactionStatus::Confirmed),
+        ];
+
+        assert_eq!(link_refunds(&mut transactions, Duration::days(30)), 0);
+        assert_eq!(transactions[0].status, TransactionStatus::Refunded);
+    }
+
+    #[test]
+    fn link_refunds_with_raw_ids_in_memos() -> Result<(), anyhow::Error> {
+        let items = r#"[
+            {
+                "id": "purchase",
+                "action": "DEBIT",
+                "amount": 4500,
+                "status": "CONFIRMED",
+                "createdAt": "2023-06-10T20:00:00.000Z",
+                "data": { "merchantName": "Cinema" }
+            },
+            {
+                "id": "refund",
+                "action": "DEBIT",
+                "amount": 4500,
+                "status": "REFUNDED",
+                "createdAt": "2023-06-15T22:10:00.000Z",
+                "data": { "merchantName": "Cinema" }
+            }
+        ]"#;
+        // Every MEMO ends with its own id, so only the payee can tell they belong together
+        let options = ConversionOptions::default().with_include_raw_id(true);
+        let mut transactions = statement_to_transactions(serde_json::from_str(items)?, &options);
+
+        assert_eq!(link_refunds(&mut transactions, Duration::days(30)), 1);
+
+        let refund = transactions
+            .iter()
+            .find(|t| t.id == "refund")
+            .expect("refund should be converted");
+        assert_eq!(refund.memo, "Cinema [id:refund] [refund:purchase]");
+        assert_eq!(refund.status, TransactionStatus::Confirmed);
+
+        Ok(())
+    }
+}
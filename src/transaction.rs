@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::currency::{format_amount, STATEMENT_CURRENCY};
+
+/// Timezone Caju reports statements in.
+pub const STATEMENT_TIMEZONE: Tz = chrono_tz::America::Sao_Paulo;
+
+/// A provider agnostic transaction. Every output format is rendered from a list of these.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transaction {
+    pub id: String,
+    pub date: DateTime<Utc>,
+    /// When the transaction was initiated, for providers that report it apart from when it was
+    /// posted (`date`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initiated_at: Option<DateTime<Utc>>,
+    /// Signed amount in cents. Negative for money leaving the account.
+    pub amount_cents: i64,
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Merchant category code (MCC), when the provider reports one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category_code: Option<String>,
+    pub payee: String,
+    pub memo: String,
+    pub status: TransactionStatus,
+}
+
+impl Transaction {
+    /// Date of the transaction in the timezone statements are reported in.
+    pub fn local_date(&self) -> DateTime<Tz> {
+        self.date.with_timezone(&STATEMENT_TIMEZONE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Eq, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+    Confirmed,
+    Refunded,
+    Pending,
+    Unknown,
+}
+
+/// Which sign money leaving the account gets. Actual expects outflows to be negative on asset
+/// accounts but positive on liability (credit) accounts.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum SignConvention {
+    /// Outflows are negative and inflows positive.
+    #[default]
+    Asset,
+    /// Outflows are positive and inflows negative.
+    Liability,
+}
+
+impl SignConvention {
+    pub fn apply(self, transactions: &mut [Transaction]) {
+        if let SignConvention::Liability = self {
+            for transaction in transactions {
+                transaction.amount_cents = -transaction.amount_cents;
+            }
+        }
+    }
+}
+
+/// Splits transactions into debits (money leaving the account) and credits, in the asset sign
+/// convention.
+pub fn split_by_sign(transactions: Vec<Transaction>) -> (Vec<Transaction>, Vec<Transaction>) {
+    transactions.into_iter().partition(|t| t.amount_cents < 0)
+}
+
+/// Splits transactions by the calendar month they were posted in, in the statement timezone, as
+/// (year, month) keys in chronological order.
+pub fn split_by_month(transactions: Vec<Transaction>) -> Vec<((i32, u32), Vec<Transaction>)> {
+    let mut months: BTreeMap<(i32, u32), Vec<Transaction>> = BTreeMap::new();
+    for transaction in transactions {
+        let date = transaction.local_date();
+        months
+            .entry((date.year(), date.month()))
+            .or_default()
+            .push(transaction);
+    }
+    months.into_iter().collect()
+}
+
+/// First and last day of a calendar month, as statement periods are represented.
+pub fn month_bounds(year: i32, month: u32) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let last = first
+        .checked_add_months(chrono::Months::new(1))?
+        .pred_opt()?;
+    Some((start_of_day(first)?, start_of_day(last)?))
+}
+
+/// First instant of a day in the statement timezone. That is midnight, except on the days
+/// daylight saving time started at midnight, when the day began at 01:00.
+pub fn start_of_day(date: NaiveDate) -> Option<DateTime<Utc>> {
+    (0..=1)
+        .filter_map(|hour| date.and_hms_opt(hour, 0, 0))
+        .find_map(|time| STATEMENT_TIMEZONE.from_local_datetime(&time).earliest())
+        .map(|datetime| datetime.with_timezone(&Utc))
+}
+
+/// First and last date among the transactions, if there are any.
+pub fn date_range(transactions: &[Transaction]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let first = transactions.iter().map(|t| t.date).min()?;
+    let last = transactions.iter().map(|t| t.date).max()?;
+    Some((first, last))
+}
+
+/// Appends the balance after each transaction to its memo, e.g. "iFood | saldo: 123,45", summing
+/// amounts in the order given on top of `opening_cents`.
+pub fn annotate_balances(transactions: &mut [Transaction], opening_cents: i64) {
+    let mut balance = opening_cents;
+    for transaction in transactions {
+        balance += transaction.amount_cents;
+        transaction.memo = format!("{} | saldo: {}", transaction.memo, format_brl(balance));
+    }
+}
+
+/// Renders the first `limit` transactions as a plain text table of their date, amount, type, payee
+/// and status, ending with how many were left out. A limit of zero shows them all.
+pub fn preview_table(transactions: &[Transaction], limit: usize) -> String {
+    let shown = match limit {
+        0 => transactions.len(),
+        limit => limit.min(transactions.len()),
+    };
+    let mut rows = vec![["Date", "Amount", "Type", "Payee", "Status"].map(str::to_string)];
+    for transaction in &transactions[..shown] {
+        rows.push([
+            transaction.local_date().format("%F").to_string(),
+            format_amount(transaction.amount_cents, STATEMENT_CURRENCY),
+            transaction.type_.clone(),
+            transaction.payee.clone(),
+            format!("{:?}", transaction.status).to_lowercase(),
+        ]);
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let line = format!(
+            "{:<date$}  {:>amount$}  {:<type_$}  {:<payee$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            date = widths[0],
+            amount = widths[1],
+            type_ = widths[2],
+            payee = widths[3],
+        );
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    if shown < transactions.len() {
+        table.push_str(&format!("... and {} more\n", transactions.len() - shown));
+    }
+    table
+}
+
+/// Prefixes each memo with the provider the transaction came from, e.g. "[Caju] iFood", to tell
+/// cards apart in an account fed by several providers. Payees and ids are left as they are.
+pub fn tag_provider(transactions: &mut [Transaction], provider: &str) {
+    for transaction in transactions {
+        transaction.memo = format!("[{}] {}", provider, transaction.memo);
+    }
+}
+
+/// Transaction ids repeated in `transactions`, each listed once, in the order they first repeat.
+pub fn duplicate_ids(transactions: &[Transaction]) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    let mut duplicates = vec![];
+    for transaction in transactions {
+        if !seen.insert(transaction.id.as_str()) && !duplicates.contains(&transaction.id.as_str()) {
+            duplicates.push(transaction.id.as_str());
+        }
+    }
+    duplicates
+}
+
+/// Gives every transaction whose id was already used by an earlier one a `-2`, `-3`, ... suffix,
+/// so importers don't merge them into one. Returns how many ids were changed.
+pub fn disambiguate_ids(transactions: &mut [Transaction]) -> usize {
+    let mut used: HashSet<String> = transactions.iter().map(|t| t.id.clone()).collect();
+    let mut seen = HashSet::new();
+    let mut changed = 0;
+    for transaction in transactions {
+        if seen.insert(transaction.id.clone()) {
+            continue;
+        }
+        let id = (2..)
+            .map(|n| format!("{}-{}", transaction.id, n))
+            .find(|id| !used.contains(id))
+            .expect("some suffix should be unused");
+        used.insert(id.clone());
+        seen.insert(id.clone());
+        transaction.id = id;
+        changed += 1;
+    }
+    changed
+}
+
+/// Cuts payees and memos longer than `max_chars` characters, ending them with an ellipsis.
+/// Returns how many transactions were shortened. A limit of zero leaves them untouched.
+pub fn truncate_descriptions(transactions: &mut [Transaction], max_chars: usize) -> usize {
+    if max_chars == 0 {
+        return 0;
+    }
+
+    let mut truncated = 0;
+    for transaction in transactions {
+        let payee = truncate(&transaction.payee, max_chars);
+        let memo = truncate(&transaction.memo, max_chars);
+        if payee.is_some() || memo.is_some() {
+            truncated += 1;
+        }
+        if let Some(payee) = payee {
+            transaction.payee = payee;
+        }
+        if let Some(memo) = memo {
+            transaction.memo = memo;
+        }
+    }
+    truncated
+}
+
+/// Shortens `text` to at most `max_chars` characters, the last one being an ellipsis. Works on
+/// chars so multibyte characters are never split.
+fn truncate(text: &str, max_chars: usize) -> Option<String> {
+    if text.chars().count() <= max_chars {
+        return None;
+    }
+    let mut shortened: String = text.chars().take(max_chars - 1).collect();
+    shortened.push('…');
+    Some(shortened)
+}
+
+/// Formats cents the way Brazilian statements do, with a decimal comma.
+fn format_brl(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!(
+        "{}{},{:02}",
+        sign,
+        cents.unsigned_abs() / 100,
+        cents.unsigned_abs() % 100
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::transaction::{
+        annotate_balances, date_range, disambiguate_ids, duplicate_ids, month_bounds,
+        preview_table, split_by_month, split_by_sign, tag_provider, truncate_descriptions,
+        SignConvention, Transaction, TransactionStatus,
+    };
+
+    fn transaction(amount_cents: i64, type_: &str) -> Transaction {
+        Transaction {
+            id: "abc".to_string(),
+            date: Utc.with_ymd_and_hms(2023, 6, 1, 12, 30, 0).unwrap(),
+            initiated_at: None,
+            amount_cents,
+            type_: type_.to_string(),
+            category_code: None,
+            payee: "iFood".to_string(),
+            memo: "iFood".to_string(),
+            status: TransactionStatus::Confirmed,
+        }
+    }
+
+    #[test]
+    fn apply_sign_conventions() {
+        let mut transactions = vec![transaction(-1050, "DEBIT"), transaction(5000, "CREDIT")];
+
+        SignConvention::Asset.apply(&mut transactions);
+        let amounts: Vec<_> = transactions.iter().map(|t| t.amount_cents).collect();
+        assert_eq!(amounts, vec![-1050, 5000]);
+
+        SignConvention::Liability.apply(&mut transactions);
+        let amounts: Vec<_> = transactions.iter().map(|t| t.amount_cents).collect();
+        assert_eq!(amounts, vec![1050, -5000]);
+    }
+
+    #[test]
+    fn serialize_json() -> Result<(), anyhow::Error> {
+        let transaction = transaction(-1050, "DEBIT");
+
+        assert_eq!(
+            serde_json::to_value(&transaction)?,
+            serde_json::json!({
+                "id": "abc",
+                "date": "2023-06-01T12:30:00Z",
+                "amount_cents": -1050,
+                "type": "DEBIT",
+                "payee": "iFood",
+                "memo": "iFood",
+                "status": "confirmed",
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotate_running_balances() {
+        let mut transactions = vec![
+            transaction(-1050, "DEBIT"),
+            transaction(5000, "CREDIT"),
+            transaction(-20000, "DEBIT"),
+        ];
+
+        annotate_balances(&mut transactions, 12345);
+
+        let memos: Vec<_> = transactions.iter().map(|t| t.memo.as_str()).collect();
+        assert_eq!(
+            memos,
+            vec![
+                "iFood | saldo: 112,95",
+                "iFood | saldo: 162,95",
+                "iFood | saldo: -37,05",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncate_long_descriptions() {
+        let mut long = transaction(-100, "DEBIT");
+        long.memo = "a".repeat(400);
+        // "ã" takes two bytes and lands right at the limit
+        let mut multibyte = transaction(-100, "DEBIT");
+        multibyte.payee = format!("{}ão", "a".repeat(3));
+        let mut transactions = vec![long, multibyte, transaction(-100, "DEBIT")];
+
+        assert_eq!(truncate_descriptions(&mut transactions, 255), 1);
+        assert_eq!(transactions[0].memo.chars().count(), 255);
+        assert!(transactions[0].memo.ends_with("a…"));
+        assert_eq!(transactions[0].payee, "iFood");
+
+        assert_eq!(truncate_descriptions(&mut transactions, 5), 1);
+        assert_eq!(transactions[1].payee, "aaaão");
+        assert_eq!(transactions[1].memo, "iFood");
+
+        assert_eq!(truncate_descriptions(&mut transactions, 4), 3);
+        assert_eq!(transactions[1].payee, "aaa…");
+        assert_eq!(transactions[2].payee, "iFo…");
+    }
+
+    #[test]
+    fn preview_first_transactions() {
+        let mut credit = transaction(500, "CREDIT");
+        credit.payee = "Caju".to_string();
+        credit.status = TransactionStatus::Pending;
+        let transactions = vec![
+            transaction(-100, "DEBIT"),
+            credit,
+            transaction(-100, "DEBIT"),
+        ];
+
+        assert_eq!(
+            preview_table(&transactions, 2),
+            concat!(
+                "Date        Amount  Type    Payee  Status\n",
+                "2023-06-01   -1.00  DEBIT   iFood  confirmed\n",
+                "2023-06-01    5.00  CREDIT  Caju   pending\n",
+                "... and 1 more\n",
+            )
+        );
+        assert_eq!(preview_table(&transactions, 0).lines().count(), 4);
+        assert_eq!(preview_table(&transactions, 20).lines().count(), 4);
+    }
+
+    #[test]
+    fn tag_memos_with_provider() {
+        let mut transactions = vec![transaction(-100, "DEBIT"), transaction(500, "CREDIT")];
+
+        tag_provider(&mut transactions, "Caju");
+
+        let memos: Vec<_> = transactions.iter().map(|t| t.memo.as_str()).collect();
+        assert_eq!(memos, vec!["[Caju] iFood", "[Caju] iFood"]);
+        assert_eq!(transactions[0].payee, "iFood");
+        assert_eq!(transactions[0].id, "abc");
+    }
+
+    #[test]
+    fn disambiguate_duplicate_ids() {
+        // Two identical purchases in the same second get the same content hash
+        let mut transactions = vec![
+            transaction(-100, "DEBIT"),
+            transaction(-100, "DEBIT"),
+            transaction(-100, "DEBIT"),
+            transaction(-200, "DEBIT"),
+        ];
+        transactions[3].id = "abc-2".to_string();
+
+        assert_eq!(duplicate_ids(&transactions), vec!["abc"]);
+        assert_eq!(disambiguate_ids(&mut transactions), 2);
+
+        let ids: Vec<_> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["abc", "abc-3", "abc-4", "abc-2"]);
+        assert!(duplicate_ids(&transactions).is_empty());
+        assert_eq!(disambiguate_ids(&mut transactions), 0);
+    }
+
+    #[test]
+    fn split_debits_from_credits() {
+        let mut deposit = transaction(50000, "CREDIT");
+        deposit.date += Duration::days(3);
+        let transactions = vec![
+            transaction(-1050, "DEBIT"),
+            deposit,
+            transaction(-200, "DEBIT"),
+        ];
+        let total = transactions.len();
+
+        let (debits, credits) = split_by_sign(transactions);
+
+        assert_eq!(debits.len(), 2);
+        assert_eq!(credits.len(), 1);
+        assert_eq!(debits.len() + credits.len(), total);
+        assert!(debits.iter().all(|t| t.amount_cents < 0));
+
+        let (start, end) = date_range(&credits).expect("credits are not empty");
+        assert_eq!(start, end);
+        assert_eq!(start.format("%F").to_string(), "2023-06-04");
+        assert!(date_range(&[]).is_none());
+    }
+
+    #[test]
+    fn split_across_month_boundary() {
+        let mut last_night = transaction(-100, "DEBIT");
+        // 01:00 UTC on July 1st is still June 30th in BRT
+        last_night.date = Utc.with_ymd_and_hms(2023, 7, 1, 1, 0, 0).unwrap();
+        let mut july = transaction(-200, "DEBIT");
+        july.date = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+        let transactions = vec![july, transaction(-300, "DEBIT"), last_night];
+
+        let months: Vec<_> = split_by_month(transactions)
+            .into_iter()
+            .map(|(month, transactions)| {
+                let amounts: Vec<_> = transactions.iter().map(|t| t.amount_cents).collect();
+                (month, amounts)
+            })
+            .collect();
+        assert_eq!(
+            months,
+            vec![((2023, 6), vec![-300, -100]), ((2023, 7), vec![-200])]
+        );
+
+        let (start, end) = month_bounds(2023, 6).expect("June is a valid month");
+        assert_eq!(start.format("%F").to_string(), "2023-06-01");
+        assert_eq!(end.format("%F").to_string(), "2023-06-30");
+        let (_, end) = month_bounds(2024, 2).expect("February is a valid month");
+        assert_eq!(end.format("%F").to_string(), "2024-02-29");
+    }
+}
@@ -0,0 +1,81 @@
+use crate::transaction::{Transaction, TransactionStatus};
+
+/// Drops transfers between the purses of the same card, such as moving balance from food to
+/// flexible. Caju doesn't flag them, but they show up as a debit and a credit of the same amount
+/// at the same instant, so both legs of each such pair are removed. Returns how many pairs were
+/// dropped.
+pub fn drop_internal_transfers(transactions: &mut Vec<Transaction>) -> usize {
+    let mut transfer = vec![false; transactions.len()];
+    let mut dropped = 0;
+
+    for debit in 0..transactions.len() {
+        if transfer[debit]
+            || transactions[debit].amount_cents >= 0
+            || transactions[debit].status != TransactionStatus::Confirmed
+        {
+            continue;
+        }
+
+        let credit = (0..transactions.len()).find(|&i| {
+            let (credit, debit) = (&transactions[i], &transactions[debit]);
+            !transfer[i]
+                && credit.status == TransactionStatus::Confirmed
+                && credit.amount_cents == -debit.amount_cents
+                && credit.date == debit.date
+        });
+        let Some(credit) = credit else {
+            continue;
+        };
+        transfer[debit] = true;
+        transfer[credit] = true;
+        dropped += 1;
+    }
+
+    let mut index = 0;
+    transactions.retain(|_| {
+        index += 1;
+        !transfer[index - 1]
+    });
+    dropped
+}
+
+#[cfg(test)]
+mod test {
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::transaction::{Transaction, TransactionStatus};
+    use crate::transfer::drop_internal_transfers;
+
+    fn transaction(id: &str, amount_cents: i64) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            date: Utc.with_ymd_and_hms(2023, 6, 5, 12, 0, 0).unwrap(),
+            initiated_at: None,
+            amount_cents,
+            type_: if amount_cents < 0 { "DEBIT" } else { "CREDIT" }.to_string(),
+            category_code: None,
+            payee: "Transferência entre saldos".to_string(),
+            memo: "Transferência entre saldos".to_string(),
+            status: TransactionStatus::Confirmed,
+        }
+    }
+
+    #[test]
+    fn drop_both_legs_of_a_transfer() {
+        let mut later_credit = transaction("later-credit", 5000);
+        later_credit.date += Duration::minutes(1);
+        let mut transactions = vec![
+            transaction("purchase", -2590),
+            transaction("from-food", -5000),
+            transaction("to-flexible", 5000),
+            // Same amount, but not at the same instant as a debit
+            later_credit,
+        ];
+
+        assert_eq!(drop_internal_transfers(&mut transactions), 1);
+
+        let ids: Vec<_> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["purchase", "later-credit"]);
+    }
+}
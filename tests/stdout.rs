@@ -0,0 +1,102 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+
+use xml::reader::{EventReader, XmlEvent};
+
+/// Answers each incoming request with the next of `bodies`, closing the connection afterwards.
+/// Returns the url to reach it.
+fn mock_server(bodies: Vec<&'static str>) -> std::io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    std::thread::spawn(move || {
+        for body in bodies {
+            let Ok((mut socket, _)) = listener.accept() else {
+                return;
+            };
+            let mut request = vec![];
+            let mut buffer = [0; 4096];
+            while let Ok(read) = socket.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                let Some(head_end) = text.find("\r\n\r\n") else {
+                    continue;
+                };
+                let content_length = text[..head_end]
+                    .lines()
+                    .find_map(|line| {
+                        line.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .and_then(|length| length.trim().parse::<usize>().ok())
+                    })
+                    .unwrap_or(0);
+                if request.len() >= head_end + 4 + content_length {
+                    break;
+                }
+            }
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes());
+        }
+    });
+
+    Ok(format!("http://{}", address))
+}
+
+#[test]
+fn write_only_ofx_to_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    let base_url = mock_server(vec![
+        r#"{ "bearerToken": "fresh" }"#,
+        include_str!("../fixtures/caju_statement.json"),
+    ])?;
+    // No .env to pick up settings from
+    let name = format!("caju-importer-{}-stdout", std::process::id());
+    let dir = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&dir)?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_caju-actual-budget-importer"))
+        .current_dir(&dir)
+        .env_clear()
+        .args(["--base-url", &base_url])
+        .args(["--user-id", "user", "--employee-id", "employee"])
+        .args(["--bearer-token", "expired", "--refresh-token", "refresh"])
+        .args(["--verbose", "6", "2023"])
+        .output()?;
+    std::fs::remove_dir_all(&dir)?;
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(output.status.success(), "{}", stderr);
+    // The filter breakdown is printed, but not to stdout
+    assert!(stderr.contains("emitted"), "{}", stderr);
+
+    assert!(stdout.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><OFX>"#));
+    assert!(stdout.ends_with("</OFX>"), "{}", stdout);
+    let mut depth = 0;
+    let mut roots = 0;
+    for event in EventReader::from_str(&stdout) {
+        match event? {
+            XmlEvent::StartElement { .. } => {
+                if depth == 0 {
+                    roots += 1;
+                }
+                depth += 1;
+            }
+            XmlEvent::EndElement { .. } => depth -= 1,
+            XmlEvent::Characters(text) => assert!(depth > 0, "stray text: {}", text),
+            _ => {}
+        }
+    }
+    assert_eq!(roots, 1);
+    assert_eq!(stdout.matches("<STMTTRN>").count(), 3);
+
+    Ok(())
+}